@@ -0,0 +1,92 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use serenity::model::id::UserId;
+
+/// Extensions of the saved avatars, tried in turn when looking one up
+const EXTENSIONS: [&str; 3] = ["png", "jpg", "webp"];
+
+/// Ringed avatars saved on disk, in a directory per user holding one file per tier named after the avatar
+/// hash it was made from, so that a lookup only checks a few paths
+pub struct AvatarCache {
+    dir: PathBuf,
+}
+
+impl AvatarCache {
+    pub fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Cannot create the cache directory {}", dir.display()))?;
+        Ok(AvatarCache { dir })
+    }
+
+    /// The saved avatar and its file name, if it was made from the avatar with this hash
+    pub fn get(&self, user: UserId, avatar_hash: &str, tier: &str) -> Option<(Vec<u8>, String)> {
+        let stem = format!("{}{}", prefix(tier), avatar_hash);
+        EXTENSIONS.iter().find_map(|extension| {
+            let data = fs::read(self.user_dir(user).join(format!("{}.{}", stem, extension))).ok()?;
+            Some((data, format!("avatar.{}", extension)))
+        })
+    }
+
+    /// Save the avatar, replacing the one made from a previous avatar of the user
+    pub fn put(&self, user: UserId, avatar_hash: &str, tier: &str, data: &[u8], extension: &str) -> anyhow::Result<()> {
+        let dir = self.user_dir(user);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Cannot create the cache directory {}", dir.display()))?;
+        let prefix = prefix(tier);
+        // only the few files of the user are listed
+        let stale = fs::read_dir(&dir).into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(&prefix)));
+        for stale in stale {
+            fs::remove_file(&stale)
+                .with_context(|| format!("Cannot remove the cached avatar {}", stale.display()))?;
+        }
+        let path = dir.join(format!("{}{}.{}", prefix, avatar_hash, extension));
+        fs::write(&path, data)
+            .with_context(|| format!("Cannot write the cached avatar {}", path.display()))?;
+        Ok(())
+    }
+
+    fn user_dir(&self, user: UserId) -> PathBuf {
+        self.dir.join(user.to_string())
+    }
+}
+
+/// Start of the file names of a tier, the tier name limited to characters safe in a file name
+fn prefix(tier: &str) -> String {
+    let tier = tier.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect::<String>();
+    format!("{}-", tier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_new_avatar_replaces_the_cached_one_of_the_tier() {
+        let dir = std::env::temp_dir().join(format!("chaosring-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = AvatarCache::new(dir.clone()).unwrap();
+        let user = UserId(1);
+
+        cache.put(user, "a1", "DAOist", b"first", "png").unwrap();
+        cache.put(user, "a1", "Fren", b"fren", "jpg").unwrap();
+        assert_eq!(cache.get(user, "a1", "DAOist"), Some((b"first".to_vec(), String::from("avatar.png"))));
+        assert_eq!(cache.get(user, "a1", "Fren"), Some((b"fren".to_vec(), String::from("avatar.jpg"))));
+        assert_eq!(cache.get(UserId(2), "a1", "DAOist"), None);
+
+        cache.put(user, "b2", "DAOist", b"second", "webp").unwrap();
+        assert_eq!(cache.get(user, "a1", "DAOist"), None);
+        assert_eq!(cache.get(user, "b2", "DAOist"), Some((b"second".to_vec(), String::from("avatar.webp"))));
+        // the other tiers of the user are kept
+        assert!(cache.get(user, "a1", "Fren").is_some());
+        assert_eq!(fs::read_dir(dir.join("1")).unwrap().count(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
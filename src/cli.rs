@@ -0,0 +1,92 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use serenity::model::prelude::AttachmentType;
+
+use crate::commands::ring::{apply_tier_ring, RingOptions};
+use crate::config::Config;
+
+const USAGE: &str = "Usage: chaosbot process --avatar <path> --tier <tier> --out <path>";
+
+/// Ring a local avatar with a tier and write the result, without connecting to Discord
+pub fn process(config: &Config, args: &[String]) -> anyhow::Result<()> {
+    let mut avatar = None;
+    let mut tier = None;
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().with_context(|| format!("Missing a value for {}\n{}", arg, USAGE))?;
+        match arg.as_str() {
+            "--avatar" => avatar = Some(PathBuf::from(value)),
+            "--tier" => tier = Some(value.as_str()),
+            "--out" => out = Some(PathBuf::from(value)),
+            _ => anyhow::bail!("Unknown argument {}\n{}", arg, USAGE),
+        }
+    }
+    let (avatar, tier, out) = match (avatar, tier, out) {
+        (Some(avatar), Some(tier), Some(out)) => (avatar, tier, out),
+        _ => anyhow::bail!(USAGE),
+    };
+
+    let tier = config.tiers.iter()
+        .find(|candidate| candidate.name.eq_ignore_ascii_case(tier))
+        .with_context(|| format!("No tier named {}", tier))?;
+    let avatar = fs::read(&avatar)
+        .with_context(|| format!("Cannot read the avatar {}", avatar.display()))?;
+
+    let attachments = apply_tier_ring(config, tier, &avatar, &RingOptions::default())?;
+    if let Some(AttachmentType::Bytes { data, .. }) = attachments.first() {
+        fs::write(&out, data)
+            .with_context(|| format!("Cannot write {}", out.display()))?;
+    }
+    println!("Wrote the {} avatar to {}", tier.name, out.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chaosring-cli-{}-{}.png", name, std::process::id()))
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn rings_a_local_avatar() {
+        let ring = temp_path("ring");
+        RgbaImage::from_fn(64, 64, |x, y| {
+            let distance = (x as f32 + 0.5 - 32.0).hypot(y as f32 + 0.5 - 32.0);
+            if distance < 24.0 { Rgba([0, 0, 0, 0]) } else { Rgba([200, 30, 30, 255]) }
+        }).save(&ring).unwrap();
+        let config: Config = toml::from_str(&format!(
+            "[[tiers]]\nname = \"DAOist\"\nrole_ids = [1]\nring = {:?}", ring.display().to_string())).unwrap();
+        let avatar = temp_path("avatar");
+        RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255])).save(&avatar).unwrap();
+        let out = temp_path("out");
+
+        let avatar = avatar.display().to_string();
+        let out_arg = out.display().to_string();
+        process(&config, &args(&["--avatar", &avatar, "--tier", "daoist", "--out", &out_arg])).unwrap();
+        let ringed = image::open(&out).unwrap().to_rgba8();
+        assert_eq!(*ringed.get_pixel(32, 32), Rgba([10, 120, 240, 255]));
+        assert_eq!(*ringed.get_pixel(3, 32), Rgba([200, 30, 30, 255]));
+
+        let err = process(&config, &args(&["--avatar", &avatar, "--tier", "Fren", "--out", &out_arg])).unwrap_err();
+        assert_eq!(err.to_string(), "No tier named Fren");
+    }
+
+    #[test]
+    fn refuses_incomplete_arguments() {
+        let config: Config = toml::from_str("tiers = []").unwrap();
+        assert_eq!(process(&config, &args(&["--avatar", "avatar.png"])).unwrap_err().to_string(), USAGE);
+        assert!(process(&config, &args(&["--avatar"])).unwrap_err().to_string().starts_with("Missing a value for --avatar"));
+        assert!(process(&config, &args(&["--size", "64"])).unwrap_err().to_string().starts_with("Unknown argument --size"));
+    }
+}
@@ -1 +1,7 @@
-pub mod ring;
\ No newline at end of file
+pub mod ring;
+pub mod ring_inspect;
+pub mod ring_pregenerate;
+pub mod ring_prefs;
+pub mod ring_status;
+pub mod ring_team;
+pub mod unring;
\ No newline at end of file
@@ -1,29 +1,141 @@
-use std::{env, fmt};
+use std::fmt;
 use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::f32::consts::TAU;
+use std::hash::{BuildHasher, Hasher};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use std::io::Cursor;
 use std::path::Path;
+use std::time::{Duration, Instant};
 use anyhow::Context;
 
-use image::{DynamicImage, GenericImage, GenericImageView, ImageBuffer, ImageOutputFormat, ImageResult, Rgba, RgbaImage};
-use image::imageops::{FilterType, overlay};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use image::{ColorType, Delay, DynamicImage, Frame, GenericImageView, ImageBuffer, ImageDecoder, ImageFormat, ImageOutputFormat, ImageResult, Rgb, Rgba, RgbaImage, RgbImage};
+use image::imageops::{blur, crop_imm, FilterType, flip_horizontal, flip_vertical, overlay, replace, resize, unsharpen};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
 use image::io::Reader as ImageReader;
+use lcms2::{InfoType, Intent, Locale, PixelFormat, Profile, Transform};
 use serenity::builder::CreateApplicationCommand;
-use serenity::model::guild::Member;
-use serenity::model::prelude::{Attachment, AttachmentType, RoleId};
-use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::{AttachmentType, RoleId, UserId};
+use serenity::model::prelude::command::{CommandOptionType, CommandType};
+use tracing::{debug, error, info, warn};
 
-#[derive(Debug)]
-pub enum DaoRole {
-    Frens,
-    Regulars,
-    DAOists,
+use crate::config::{BlendMode, ChromaKey, Config, LabelPosition, Mask, OutputFormat, Shadow, Tier};
+use crate::download::AvatarSource;
+use crate::label;
+
+/// The ringed avatar (one attachment per requested size) along with the tier whose ring was applied, if any
+pub struct RingedAvatar<'a> {
+    pub tier: Option<String>,
+    pub attachments: Vec<AttachmentType<'a>>,
+    /// Advice for the user, like uploading a larger avatar
+    pub warning: Option<String>,
+}
+
+const JPEG_QUALITY: u8 = 90;
+/// Discord's limit on the length of a message
+const MAX_MESSAGE_LENGTH: usize = 2000;
+/// Floors of the reductions made to fit `max_output_bytes`
+const MIN_JPEG_QUALITY: u8 = 40;
+const MIN_CAPPED_SIDE: u32 = 128;
+/// Sizes stored in the icon of the `ico` option, 256 being the largest an ICO entry can hold
+const ICO_SIZES: [u32; 4] = [16, 32, 48, 256];
+/// Discord's requirements for sticker images
+const STICKER_SIDE: u32 = 320;
+const MAX_STICKER_BYTES: usize = 512 * 1024;
+/// Low bits of each colour channel dropped at most to fit a sticker, leaving 32 shades
+const MAX_DROPPED_COLOR_BITS: u32 = 3;
+
+/// Side of the preview sent while the full avatar is prepared
+const PREVIEW_SIDE: u32 = 96;
+
+/// Attempts at downloading the avatar, waiting twice as long after each unexpected failure
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+const FIRST_DOWNLOAD_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Options of the `/ring` command shaping the output
+#[derive(Default, Clone, PartialEq)]
+pub struct RingOptions {
+    /// Sides of the returned avatars, a single avatar at the ring's resolution when empty
+    pub sizes: Vec<u32>,
+    /// Draw the avatar over the ring
+    pub ring_behind: bool,
+    /// Resolution written in the metadata of PNG outputs
+    pub dpi: Option<u32>,
+    /// Draw the tier name on the ring
+    pub label: bool,
+    /// Cast a drop shadow under the ringed avatar
+    pub shadow: bool,
+    /// Only crop the avatar to a circle, which doesn't require any role
+    pub no_ring: bool,
+    /// Mirror the avatar horizontally
+    pub flip_h: bool,
+    /// Mirror the avatar vertically
+    pub flip_v: bool,
+    /// Stack the event ring over the tier ring
+    pub event: bool,
+    /// Percentage applied to the opacity of the ring, fully opaque when unset
+    pub ring_opacity: Option<u8>,
+    /// Upscale a small avatar to the ring rather than shrinking the ring to the avatar
+    pub keep_ring_size: bool,
+    /// Percentage of the ring drawn, clockwise from the top, the whole ring when unset
+    pub progress: Option<u8>,
+    /// Colour multiplied with the ring, to recolour a grayscale ring
+    pub tint: Option<Rgb<u8>>,
+    /// Crop the transparent margin around the ring
+    pub crop: bool,
+    /// Sharpen the resized avatar
+    pub sharpen: bool,
+    /// Send the avatar as a `data:` URI in the message rather than as a file
+    pub as_data_uri: bool,
+    /// Also send a multi-size ICO of the avatar, for use as a favicon
+    pub ico: bool,
+    /// How the ring is composited over the avatar, the configured blend mode when unset
+    pub blend_mode: Option<BlendMode>,
+    /// Also send a GIF of the ring drawing in around the avatar
+    pub animate_reveal: bool,
+    /// Send a single PNG meeting the requirements of Discord stickers instead of the avatar
+    pub sticker: bool,
+    /// Colour transform of the avatar, the ring keeping its colours
+    pub style: AvatarStyle,
+    /// Name of the alternative ring of the tier to use instead of its main one
+    pub variant: Option<String>,
+    /// Days since the member joined the server, stamped on the ring
+    pub join_days: Option<u64>,
+    /// Image of the custom emoji used as the ring instead of the one of the tier
+    pub emoji_ring: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AvatarStyle {
+    #[default]
+    Color,
+    Grayscale,
+    Sepia,
+}
+
+impl AvatarStyle {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "color" => Some(AvatarStyle::Color),
+            "grayscale" => Some(AvatarStyle::Grayscale),
+            "sepia" => Some(AvatarStyle::Sepia),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct UserRecoverableError {
     reason: String,
+    /// What the user can do about it, shown after the error
+    hint: Option<String>,
 }
 
 
@@ -35,6 +147,21 @@ impl Display for UserRecoverableError {
 
 impl Error for UserRecoverableError {}
 
+impl UserRecoverableError {
+    pub fn new(reason: impl Into<String>) -> Self {
+        UserRecoverableError { reason: reason.into(), hint: None }
+    }
+
+    pub fn with_hint(mut self, hint: Option<&str>) -> Self {
+        self.hint = hint.map(String::from);
+        self
+    }
+
+    pub fn hint(&self) -> Option<&str> {
+        self.hint.as_deref()
+    }
+}
+
 pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
     command
         .name("ring")
@@ -43,118 +170,2529 @@ pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicatio
             |option| {
                 option
                     .name("avatar")
-                    .description("A square profile picture")
+                    .description("A square profile picture (your Discord avatar when there is neither this nor a URL)")
                     .kind(CommandOptionType::Attachment)
-                    .required(true)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("url")
+                    .description("A link to a square profile picture (used when no avatar is attached)")
+                    .kind(CommandOptionType::String)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("sizes")
+                    .description("Comma-separated sides in pixels to get one avatar per size, like 512,256")
+                    .kind(CommandOptionType::String)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("ring_behind")
+                    .description("Draw the avatar over the ring instead of under it")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("dpi")
+                    .description("Print resolution stored in the PNG metadata")
+                    .kind(CommandOptionType::Integer)
+                    .min_int_value(1)
+                    .max_int_value(10000)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("label")
+                    .description("Write the name of your tier on the ring")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("shadow")
+                    .description("Add a drop shadow under the ringed avatar")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("no_ring")
+                    .description("Only crop the avatar to a circle, without any ring")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("flip_h")
+                    .description("Mirror the avatar horizontally")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("flip_v")
+                    .description("Mirror the avatar vertically")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("event")
+                    .description("Add the ring of the current event over your ring")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("ring_opacity")
+                    .description("Opacity of the ring in percent (100 by default)")
+                    .kind(CommandOptionType::Integer)
+                    .min_int_value(0)
+                    .max_int_value(100)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("keep_ring_size")
+                    .description("Output at the ring's resolution even when the avatar is smaller")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("progress")
+                    .description("Percentage of the ring drawn clockwise from the top, like a progress bar")
+                    .kind(CommandOptionType::Integer)
+                    .min_int_value(0)
+                    .max_int_value(100)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("tint")
+                    .description("Colour of the ring formatted as #rrggbb")
+                    .kind(CommandOptionType::String)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("crop")
+                    .description("Remove the transparent margin around the ring")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("sharpen")
+                    .description("Sharpen the avatar, which can look soft once shrunk")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("as_data_uri")
+                    .description("Reply with the image as a data: URI text instead of a file (small images only)")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("ico")
+                    .description("Also send a favicon-style .ico holding the avatar in 16, 32, 48 and 256 pixels")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("blend")
+                    .description("How the ring colours mix with the avatar, screen or add suiting glowing rings")
+                    .kind(CommandOptionType::String)
+                    .add_string_choice("normal", "normal")
+                    .add_string_choice("screen", "screen")
+                    .add_string_choice("add", "add")
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("animate_reveal")
+                    .description("Also send a GIF of the ring drawing in around the avatar")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("sticker")
+                    .description("Send a 320x320 PNG ready to upload as a server sticker instead")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("style")
+                    .description("Colours of the avatar, the ring keeping its own")
+                    .kind(CommandOptionType::String)
+                    .add_string_choice("color", "color")
+                    .add_string_choice("grayscale", "grayscale")
+                    .add_string_choice("sepia", "sepia")
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("variant")
+                    .description("Name of an alternative ring of your tier, like a seasonal one")
+                    .kind(CommandOptionType::String)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("emoji")
+                    .description("Custom emoji of the server (like <:name:id>) to use as the ring")
+                    .kind(CommandOptionType::String)
+                    .required(false)
             },
         )
 }
 
-pub async fn run<'a>(user: &'a Member, user_image: &'a Attachment) -> anyhow::Result<AttachmentType<'a>> {
-    let ring_path = match find_dao_role(user)? {
-        DaoRole::Frens => { load_env_var("CHAOSRING_FRENS") }
-        DaoRole::Regulars => { load_env_var("CHAOSRING_REGULARS") }
-        DaoRole::DAOists => { load_env_var("CHAOSRING_DAOISTS") }
-    }?;
+/// Name of the message context-menu command ringing the image attached to a message
+pub const MESSAGE_COMMAND_NAME: &str = "Apply ChaosDAO Ring";
 
-    let ring = ImageReader::open(Path::new(&ring_path))?
-        .decode()?;
+/// Name of the user context-menu command ringing the avatar of a member
+pub const USER_COMMAND_NAME: &str = "Ring this user's avatar";
 
-    let avatar = user_image.download().await?;
-    let avatar = image::load_from_memory(&avatar)
-        .and_then(|avatar| overlay_ring(&avatar.to_rgba8(), &ring.to_rgba8()))?;
+pub fn register_message_command(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name(MESSAGE_COMMAND_NAME)
+        .kind(CommandType::Message)
+}
 
-    let buf: Vec<u8> = Vec::with_capacity(avatar.as_raw().len());
-    let mut cursor: Cursor<Vec<u8>> = Cursor::new(buf);
-    avatar.write_to(&mut cursor, ImageOutputFormat::Png)?;
-    let attachment = AttachmentType::Bytes {
-        data: Cow::from(cursor.into_inner()),
-        filename: String::from("avatar.png"),
+pub fn register_user_command(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name(USER_COMMAND_NAME)
+        .kind(CommandType::User)
+}
+
+/// An avatar about to be rung, decoded once and with its tier and ring resolved once, so that the preview and the
+/// full avatar share them, even when the tier is picked at random
+pub struct PendingRing<'c> {
+    tier: &'c Tier,
+    ring: RgbaImage,
+    avatar: DecodedAvatar,
+    warning: Option<String>,
+}
+
+impl<'c> PendingRing<'c> {
+    pub fn new(config: &'c Config, roles: &[RoleId], avatar: &[u8], options: &RingOptions) -> anyhow::Result<Self> {
+        let warning = check_min_dimension(config, avatar)?;
+        let tier = resolve_tier(config, roles)?;
+        let ring = select_ring(config, tier, options)?;
+        let avatar = DecodedAvatar::new(avatar)?;
+        Ok(PendingRing { tier, ring, avatar, warning })
+    }
+
+    /// A quick low-resolution version of the ringed avatar, shown while the full one is prepared.
+    /// The avatar is shrunk before its colours are processed, and the outputs options (label, sizes...) are left out.
+    pub fn preview<'a>(&self, config: &Config, options: &RingOptions) -> anyhow::Result<AttachmentType<'a>> {
+        let (avatar, _) = prepare_decoded(config, self.avatar.downscaled(PREVIEW_SIDE), options)?;
+        let avatar = DynamicImage::ImageRgba8(avatar)
+            .resize_to_fill(PREVIEW_SIDE, PREVIEW_SIDE, FilterType::Triangle)
+            .to_rgba8();
+        // the ring is shrunk to the small avatar with a fast filter rather than the avatar upscaled to the ring
+        let ring = resize(&self.ring, PREVIEW_SIDE, PREVIEW_SIDE, FilterType::Triangle);
+        let options = RingOptions { keep_ring_size: false, ..options.clone() };
+        let preview = overlay_ring(config, &avatar, &ring, &options)?;
+        Ok(AttachmentType::Bytes {
+            data: Cow::from(encode(&preview, OutputFormat::Png, config.jpeg_background, None)?),
+            filename: String::from("preview.png"),
+        })
+    }
+
+    /// The full ringed avatar
+    pub fn ring<'a>(self, config: &Config, options: &RingOptions) -> anyhow::Result<RingedAvatar<'a>> {
+        let (avatar, input_format) = prepare_decoded(config, self.avatar, options)?;
+        let attachments = apply_ring_to_image(config, self.tier, &self.ring, avatar, input_format, options)?;
+        Ok(RingedAvatar { tier: Some(self.tier.name.clone()), attachments, warning: self.warning })
+    }
+}
+
+/// Ring the avatar with the tier of the given roles
+pub async fn run<'a>(config: &Config, roles: &[RoleId], avatar: &dyn AvatarSource, options: &RingOptions) -> anyhow::Result<RingedAvatar<'a>> {
+    let avatar = download(config, avatar).await?;
+    process_avatar(config, roles, &avatar, options)
+}
+
+/// Download the avatar, retrying the failures that are not the user's to fix and logging the downloads slower
+/// than `slow_download`. The bytes are only decoded once downloaded.
+pub async fn download(config: &Config, avatar: &dyn AvatarSource) -> anyhow::Result<Vec<u8>> {
+    let started_at = Instant::now();
+    let mut delay = FIRST_DOWNLOAD_RETRY_DELAY;
+    let mut attempt = 1;
+    let avatar = loop {
+        match avatar.download().await {
+            Ok(avatar) => break avatar,
+            Err(err) if attempt < DOWNLOAD_ATTEMPTS && err.downcast_ref::<UserRecoverableError>().is_none() => {
+                warn!("Failed to download the avatar (attempt {}/{}), retrying in {:?}: {:#}", attempt, DOWNLOAD_ATTEMPTS, delay, err);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
     };
+    let elapsed = started_at.elapsed();
+    if config.slow_download.is_some_and(|slow_download| elapsed > slow_download) {
+        warn!("Downloading the avatar took {} ms for {} bytes", elapsed.as_millis(), avatar.len());
+    }
+    Ok(avatar)
+}
+
+/// Parse a comma-separated list of sides, dropping duplicates
+pub fn parse_sizes(value: &str, max_dimension: u32) -> anyhow::Result<Vec<u32>> {
+    let mut sizes = Vec::new();
+    for size in value.split(',').map(str::trim).filter(|size| !size.is_empty()) {
+        let size = size.parse::<u32>()
+            .ok()
+            .filter(|size| *size > 0)
+            .ok_or_else(|| UserRecoverableError::new(format!("Invalid size: {}", size)))?;
+        if size > max_dimension {
+            return Err(UserRecoverableError::new(format!("Sizes cannot exceed {} pixels", max_dimension)).into());
+        }
+        if !sizes.contains(&size) {
+            sizes.push(size);
+        }
+    }
+    Ok(sizes)
+}
 
-    Ok(attachment)
+/// Overlay the user's ring on an already downloaded avatar
+pub fn process_avatar<'a>(config: &Config, roles: &[RoleId], avatar: &[u8], options: &RingOptions) -> anyhow::Result<RingedAvatar<'a>> {
+    if options.no_ring {
+        let warning = check_min_dimension(config, avatar)?;
+        let attachments = crop_to_circle(config, avatar, options)?;
+        return Ok(RingedAvatar { tier: None, attachments, warning });
+    }
+
+    PendingRing::new(config, roles, avatar, options)?.ring(config, options)
 }
 
-fn load_env_var(variable: &str) -> anyhow::Result<String> {
-    let var = env::var(variable)
-        .with_context(|| format!("No variable with name {} found in the environment", &variable))?;
-    Ok(var)
+/// Refuse avatars whose shorter side is below the hard floor, and warn about those below the soft one
+pub fn check_min_dimension(config: &Config, avatar: &[u8]) -> anyhow::Result<Option<String>> {
+    if config.min_dimension.is_none() && config.min_dimension_hard.is_none() {
+        return Ok(None);
+    }
+    // only the header is read, the avatar is decoded later
+    let (width, height) = ImageReader::new(Cursor::new(avatar))
+        .with_guessed_format()?
+        .into_dimensions()?;
+    let side = width.min(height);
+
+    if let Some(floor) = config.min_dimension_hard.filter(|floor| side < *floor) {
+        return Err(UserRecoverableError::new(format!("The image is too small ({}x{}), it must be at least {} pixels wide and high", width, height, floor)).into());
+    }
+    Ok(config.min_dimension
+        .filter(|min_dimension| side < *min_dimension)
+        .map(|min_dimension| format!("Your image is only {}x{}, upload one of at least {}x{} for a sharper result.", width, height, min_dimension, min_dimension)))
 }
 
-fn parse_role_id(value: String) -> anyhow::Result<u64> {
-    let value = value.parse::<u64>()?;
-    Ok(value)
+/// Ring a generated sample avatar with every tier and log the outcome, to catch a misconfiguration on startup
+pub fn self_test(config: &Config) {
+    match self_test_outcomes(config) {
+        Ok(outcomes) => for (tier, outcome) in outcomes {
+            match outcome {
+                Ok(()) => info!("Self-test: the {} ring works", tier),
+                Err(err) => error!("Self-test: the {} ring failed: {:#}", tier, err),
+            }
+        },
+        Err(err) => error!("Self-test: cannot encode the sample avatar: {}", err),
+    }
 }
 
-fn find_dao_role(member: &Member) -> anyhow::Result<DaoRole> {
-    let user_roles: &Vec<RoleId> = &member.roles;
+/// Whether ringing the sample avatar works, for each tier
+fn self_test_outcomes(config: &Config) -> anyhow::Result<Vec<(&str, anyhow::Result<()>)>> {
+    let sample = RgbaImage::from_fn(64, 64, |x, y| Rgba([(x * 4) as u8, (y * 4) as u8, 128, 255]));
+    let sample = encode(&sample, OutputFormat::Png, config.jpeg_background, None)?;
+    Ok(config.tiers.iter()
+        .map(|tier| (tier.name.as_str(), apply_tier_ring(config, tier, &sample, &RingOptions::default()).map(|_| ())))
+        .collect())
+}
 
-    let role_fren_id = load_env_var("DAO_ROLE_FREN")
-        .and_then(parse_role_id)?;
-    let role_regular_id = load_env_var("DAO_ROLE_REGULAR")
-        .and_then(parse_role_id)?;
-    let role_daoist_id = load_env_var("DAO_ROLE_DAOIST")
-        .and_then(parse_role_id)?;
+pub fn apply_tier_ring<'a>(config: &Config, tier: &Tier, avatar: &[u8], options: &RingOptions) -> anyhow::Result<Vec<AttachmentType<'a>>> {
+    let (avatar, input_format) = prepare_avatar(config, avatar, options)?;
+    apply_tier_ring_to_image(config, tier, avatar, input_format, options)
+}
 
-    let fren: RoleId = RoleId(role_fren_id);
-    let regular: RoleId = RoleId(role_regular_id);
-    let daoist: RoleId = RoleId(role_daoist_id);
+/// Ring an avatar already decoded by `prepare_avatar`, `input_format` being the format it was uploaded in
+pub fn apply_tier_ring_to_image<'a>(config: &Config, tier: &Tier, avatar: RgbaImage, input_format: Option<OutputFormat>, options: &RingOptions) -> anyhow::Result<Vec<AttachmentType<'a>>> {
+    let ring = select_ring(config, tier, options)?;
+    apply_ring_to_image(config, tier, &ring, avatar, input_format, options)
+}
 
-    if user_roles.contains(&daoist) {
-        Ok(DaoRole::DAOists)
-    } else if user_roles.contains(&regular) {
-        Ok(DaoRole::Regulars)
-    } else if user_roles.contains(&fren) {
-        Ok(DaoRole::Frens)
-    } else {
-        let inner = UserRecoverableError { reason: String::from("User is not a DAOist, regular or fren") };
-        Err(anyhow::Error::new(inner))
+/// The ring of the tier picked by the options: its variant or the custom emoji, under the event ring if requested
+fn select_ring(config: &Config, tier: &Tier, options: &RingOptions) -> anyhow::Result<RgbaImage> {
+    let ring_path = match &options.variant {
+        Some(variant) => tier.variants.get(variant).ok_or_else(|| {
+            let mut variants = tier.variants.keys().map(String::as_str).collect::<Vec<&str>>();
+            variants.sort();
+            let available = if variants.is_empty() { String::from("none") } else { variants.join(", ") };
+            UserRecoverableError::new(format!("The {} tier has no {} ring (available: {})", tier.name, variant, available))
+        })?,
+        None => &tier.ring,
+    };
+    let mut ring = match &options.emoji_ring {
+        Some(emoji) => emoji_ring(emoji)?,
+        None => load_ring(ring_path, config.chroma_key.as_ref())?,
+    };
+    if options.event {
+        let event_ring = config.event_ring.as_ref()
+            .ok_or_else(|| UserRecoverableError::new("There is no event ring at the moment"))?;
+        ring = stack_rings(&ring, &load_ring(event_ring, config.chroma_key.as_ref())?, config.ring_filter);
     }
+    Ok(ring)
 }
 
-fn overlay_ring(avatar: &RgbaImage, ring: &RgbaImage) -> ImageResult<ImageBuffer<Rgba<u8>, Vec<u8>>> {
-    println!("dimensions: avatar @ {:?}, ring @ {:?}", avatar.dimensions(), ring.dimensions());
+/// Overlay the ring selected for the tier on an avatar already decoded by `prepare_avatar`
+fn apply_ring_to_image<'a>(config: &Config, tier: &Tier, ring: &RgbaImage, avatar: RgbaImage, input_format: Option<OutputFormat>, options: &RingOptions) -> anyhow::Result<Vec<AttachmentType<'a>>> {
+    let reveal = if options.animate_reveal {
+        Some(reveal_gif(config, &avatar, ring, tier.scale, options)?)
+    } else {
+        None
+    };
+    let mut avatar = overlay_ring(config, &avatar, ring, options)?;
 
-    let mut ring = DynamicImage::ImageRgba8(ring.clone());
-    let avatar = DynamicImage::ImageRgba8(avatar.clone());
-    // images must be square so one dimension is enough
-    let avatar_side = avatar.width();
-    let ring_side = ring.width();
-    if ring_side > avatar_side {
-        ring = ring.resize_to_fill(avatar_side, avatar_side, FilterType::Nearest);
+    // the text is left out rather than failing the command when the font is gone or broken, or the text doesn't fit
+    let font = match &tier.label_font {
+        Some(path) if options.label || options.join_days.is_some() => match label::Font::load(path) {
+            Ok(font) => Some(font),
+            Err(err) => {
+                warn!("Cannot load the label font of the {} tier, leaving the text out: {:#}", tier.name, err);
+                None
+            }
+        },
+        _ => Some(label::Font::bundled()),
+    };
+    if let (true, Some(font)) = (options.label, &font) {
+        let ring_width = get_ring_width(&DynamicImage::ImageRgba8(ring.clone())) * avatar.width() / ring.width();
+        if !label::draw_label(&mut avatar, font, &tier.name, config.label_position, ring_width, tier.label_color) {
+            warn!("The {} label doesn't fit on a {}px avatar, leaving it out", tier.name, avatar.width());
+        }
+    }
+    if let (Some(days), Some(font)) = (options.join_days, &font) {
+        // on the other side of the ring than the label
+        let position = match config.label_position {
+            LabelPosition::Top => LabelPosition::Bottom,
+            LabelPosition::Bottom => LabelPosition::Top,
+        };
+        let ring_width = get_ring_width(&DynamicImage::ImageRgba8(ring.clone())) * avatar.width() / ring.width();
+        if !label::draw_label(&mut avatar, font, &format!("{}D", days), position, ring_width, tier.label_color) {
+            warn!("The join badge doesn't fit on a {}px avatar, leaving it out", avatar.width());
+        }
     }
-    let ring_side = ring.width();
-    let circumference_width = get_ring_width(&ring);
-    let scaled_avatar = avatar.resize_to_fill(ring_side - 2 * circumference_width,
-                                              ring_side - 2 * circumference_width,
-                                              FilterType::Nearest);
-
-    let mut buffer = RgbaImage::new(ring_side, ring_side);
-    buffer.copy_from(&scaled_avatar, circumference_width, circumference_width)?;
-    overlay(&mut buffer, &ring, 0, 0);
-    let cx = (buffer.width() / 2) as f32;
-    let cy = (buffer.height() / 2) as f32;
-    apply_transparency(&mut buffer, ring_side / 2, cx, cy);
 
-    Ok(buffer)
+    if let Some(scale) = tier.scale {
+        let side = ((avatar.width() as f32 * scale).round() as u32).max(1);
+        avatar = resize(&avatar, side, side, FilterType::Lanczos3);
+    }
+
+    let format = tier.format
+        .or(if config.preserve_format { input_format } else { None })
+        .unwrap_or(OutputFormat::Png);
+    let mut attachments = finish(config, avatar, format, options)?;
+    if let Some(reveal) = reveal {
+        attachments.push(AttachmentType::Bytes { data: Cow::from(reveal), filename: String::from("avatar-reveal.gif") });
+    }
+    Ok(attachments)
 }
 
-/// Apply transparency to the image buffer pixels outside the ring
-fn apply_transparency(buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, radius: u32, cx: f32, cy: f32) {
-    buffer.enumerate_pixels_mut()
-        .for_each(|(x, y, px)| {
-            let distance = (x as f32 - cx).hypot(y as f32 - cy);
-            if distance > radius as f32 {
-                px[3] = 0;
+/// A looping GIF of the ring drawn in progressively over the avatar, the arc growing at each frame
+fn reveal_gif(config: &Config, avatar: &RgbaImage, ring: &RgbaImage, scale: Option<f32>, options: &RingOptions) -> anyhow::Result<Vec<u8>> {
+    let delay = Delay::from_numer_denom_ms(config.reveal.frame_ms, 1);
+    let frames = (1..=config.reveal.frames)
+        .map(|frame| {
+            let progress = (frame * 100 / config.reveal.frames) as u8;
+            let frame_options = RingOptions { progress: Some(progress), ..options.clone() };
+            let mut image = overlay_ring(config, avatar, ring, &frame_options)?;
+            if let Some(scale) = scale {
+                let side = ((image.width() as f32 * scale).round() as u32).max(1);
+                image = resize(&image, side, side, FilterType::Lanczos3);
             }
+            Ok(Frame::from_parts(image, 0, 0, delay))
+        })
+        .collect::<ImageResult<Vec<Frame>>>()?;
+
+    let mut buf: Vec<u8> = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut buf);
+        encoder.set_repeat(Repeat::Infinite)?;
+        encoder.encode_frames(frames)?;
+    }
+    Ok(buf)
+}
+
+/// Draw the `top` ring over the `bottom` one, resized to the same side.
+/// The band of the result covers both rings so the avatar fits inside the innermost edge.
+fn stack_rings(bottom: &RgbaImage, top: &RgbaImage, filter: FilterType) -> RgbaImage {
+    let mut stacked = bottom.clone();
+    let top = if top.dimensions() == bottom.dimensions() {
+        top.clone()
+    } else {
+        resize(top, bottom.width(), bottom.height(), filter)
+    };
+    overlay_premultiplied(&mut stacked, &top, 0, 0, BlendMode::Normal);
+    stacked
+}
+
+/// An uploaded avatar decoded upright, its colours not processed yet so that it can be shrunk first
+pub struct DecodedAvatar {
+    image: DynamicImage,
+    profile: Option<Vec<u8>>,
+    input_format: Option<OutputFormat>,
+}
+
+impl DecodedAvatar {
+    pub fn new(avatar: &[u8]) -> anyhow::Result<Self> {
+        let input_format = image::guess_format(avatar).ok().and_then(output_format_for);
+        let orientation = exif_orientation(avatar);
+        let profile = icc_profile(avatar);
+        let image = apply_orientation(decode(avatar)?, orientation);
+        Ok(DecodedAvatar { image, profile, input_format })
+    }
+
+    /// A copy whose shorter side is shrunk to `side`, keeping the proportions
+    fn downscaled(&self, side: u32) -> Self {
+        let (width, height) = self.image.dimensions();
+        let shorter = width.min(height);
+        let image = if shorter > side {
+            let scale = |length: u32| ((length as u64 * side as u64 / shorter as u64) as u32).max(1);
+            self.image.thumbnail_exact(scale(width), scale(height))
+        } else {
+            self.image.clone()
+        };
+        DecodedAvatar { image, profile: self.profile.clone(), input_format: self.input_format }
+    }
+}
+
+/// Decode the uploaded avatar upright, mirrored as requested and padded if almost square, along with its format
+pub fn prepare_avatar(config: &Config, avatar: &[u8], options: &RingOptions) -> anyhow::Result<(RgbaImage, Option<OutputFormat>)> {
+    prepare_decoded(config, DecodedAvatar::new(avatar)?, options)
+}
+
+/// Convert the colours of the decoded avatar, mirror it as requested and pad it if almost square
+fn prepare_decoded(config: &Config, avatar: DecodedAvatar, options: &RingOptions) -> anyhow::Result<(RgbaImage, Option<OutputFormat>)> {
+    let DecodedAvatar { image, profile, input_format } = avatar;
+    // the PNG decoder expands indexed images to RGBA, the palette entries listed in tRNS becoming
+    // (semi-)transparent, so they reach the overlay with their corners and colours intact
+    let mut avatar = image.to_rgba8();
+    if let Some(profile) = profile {
+        if let Err(err) = convert_to_srgb(&mut avatar, &profile) {
+            warn!("Cannot convert the colour profile of the avatar, using its colours as they are: {:#}", err);
+        }
+    }
+
+    match options.style {
+        AvatarStyle::Color => {}
+        AvatarStyle::Grayscale => avatar = DynamicImage::ImageRgba8(avatar).grayscale().to_rgba8(),
+        AvatarStyle::Sepia => apply_sepia(&mut avatar),
+    }
+
+    if options.flip_h {
+        avatar = flip_horizontal(&avatar);
+    }
+    if options.flip_v {
+        avatar = flip_vertical(&avatar);
+    }
+
+    let mut avatar = pad_near_square(avatar, config.square_tolerance);
+    if config.smart_crop {
+        avatar = smart_crop(avatar);
+    }
+    Ok((avatar, input_format))
+}
+
+/// Crop a non-square image to the square window centred on its details, found as the centre of mass of the
+/// edges along the longer side. Images without edges are left for the centred crop of the resize.
+fn smart_crop(image: RgbaImage) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    if width == height {
+        return image;
+    }
+    let gray = DynamicImage::ImageRgba8(image.clone()).to_luma8();
+    let (mut energy, mut moment) = (0.0, 0.0);
+    for y in 1..height {
+        for x in 1..width {
+            let value = gray.get_pixel(x, y)[0] as f64;
+            let gradient = (value - gray.get_pixel(x - 1, y)[0] as f64).abs() + (value - gray.get_pixel(x, y - 1)[0] as f64).abs();
+            // edges of transparent areas don't show
+            let gradient = gradient * image.get_pixel(x, y)[3] as f64 / 255.0;
+            energy += gradient;
+            let position = if width > height { x } else { y };
+            moment += gradient * position as f64;
+        }
+    }
+    if energy == 0.0 {
+        return image;
+    }
+
+    let side = width.min(height);
+    let start = ((moment / energy).round() as i64 - side as i64 / 2)
+        .clamp(0, (width.max(height) - side) as i64) as u32;
+    let (x, y) = if width > height { (start, 0) } else { (0, start) };
+    crop_imm(&image, x, y, side, side).to_image()
+}
+
+/// Tone the image in browns with the usual sepia matrix
+fn apply_sepia(image: &mut RgbaImage) {
+    for px in image.pixels_mut() {
+        let (r, g, b) = (px[0] as f32, px[1] as f32, px[2] as f32);
+        px[0] = (0.393 * r + 0.769 * g + 0.189 * b).min(255.0) as u8;
+        px[1] = (0.349 * r + 0.686 * g + 0.168 * b).min(255.0) as u8;
+        px[2] = (0.272 * r + 0.534 * g + 0.131 * b).min(255.0) as u8;
+    }
+}
+
+/// Crop the avatar to the circle inscribed in its shorter side
+fn crop_to_circle<'a>(config: &Config, avatar: &[u8], options: &RingOptions) -> anyhow::Result<Vec<AttachmentType<'a>>> {
+    let (avatar, input_format) = prepare_avatar(config, avatar, options)?;
+    let avatar = DynamicImage::ImageRgba8(avatar);
+
+    let side = avatar.width().min(avatar.height());
+    let mut avatar = avatar.resize_to_fill(side, side, FilterType::Lanczos3).to_rgba8();
+    let center = (side / 2) as f32;
+    apply_mask(&mut avatar, side / 2, center, center, &config.mask);
+
+    let format = (if config.preserve_format { input_format } else { None })
+        .unwrap_or(OutputFormat::Png);
+    finish(config, avatar, format, options)
+}
+
+/// Add the requested shadow and encode the avatar in every requested size
+fn finish<'a>(config: &Config, mut avatar: RgbaImage, format: OutputFormat, options: &RingOptions) -> anyhow::Result<Vec<AttachmentType<'a>>> {
+    if options.crop {
+        if let Some((x, y, side)) = opaque_bounds(&avatar) {
+            avatar = crop_imm(&avatar, x, y, side, side).to_image();
+        }
+    }
+    if options.shadow {
+        avatar = add_shadow(&avatar, &config.shadow);
+    }
+    if config.linear_output {
+        to_linear(&mut avatar);
+    }
+
+    let mut attachments = if options.sticker {
+        vec![AttachmentType::Bytes {
+            data: Cow::from(encode_sticker(config, &avatar)?),
+            filename: String::from("sticker.png"),
+        }]
+    } else if options.sizes.is_empty() {
+        vec![AttachmentType::Bytes {
+            data: Cow::from(encode_capped(config, &avatar, format, options.dpi)?),
+            filename: format!("avatar.{}", extension(format)),
+        }]
+    } else {
+        options.sizes.iter()
+            .map(|size| {
+                let resized = resize(&avatar, *size, *size, FilterType::Lanczos3);
+                Ok(AttachmentType::Bytes {
+                    data: Cow::from(encode_capped(config, &resized, format, options.dpi)?),
+                    filename: format!("avatar-{}.{}", size, extension(format)),
+                })
+            })
+            .collect::<anyhow::Result<Vec<AttachmentType>>>()?
+    };
+    if options.ico {
+        attachments.push(AttachmentType::Bytes {
+            data: Cow::from(encode_ico(&avatar)?),
+            filename: String::from("avatar.ico"),
         });
+    }
+
+    Ok(attachments)
 }
 
-fn get_ring_width(ring_img: &DynamicImage) -> u32 {
-    // count non-transparent pixels along the top half the Y axis (in a single column)
-    let x = ring_img.width() / 2;
-    (0..ring_img.height() / 2)
-        .map(|y| ring_img.get_pixel(x, y))
-        .map(|pixel| if pixel[3] != 0 { 1u32 } else { 0u32 })// 1 for non-transparent pixel
-        .sum()
+/// Convert the sRGB colours to linear light, alpha being linear already
+fn to_linear(image: &mut RgbaImage) {
+    let lookup = (0..=255u8)
+        .map(|value| {
+            let value = value as f32 / 255.0;
+            let linear = if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) };
+            (linear * 255.0).round() as u8
+        })
+        .collect::<Vec<u8>>();
+    for px in image.pixels_mut() {
+        for channel in 0..3 {
+            px[channel] = lookup[px[channel] as usize];
+        }
+    }
+}
+
+/// Encode the avatar as a PNG of the side and at most the size Discord accepts for stickers
+fn encode_sticker(config: &Config, avatar: &RgbaImage) -> anyhow::Result<Vec<u8>> {
+    let sticker = resize(avatar, STICKER_SIDE, STICKER_SIDE, FilterType::Lanczos3);
+    encode_png_within(config, &sticker, MAX_STICKER_BYTES)
+}
+
+/// Encode the image as a PNG of at most `max_bytes`, dropping the low bits of the colours until it fits,
+/// fewer shades compressing better
+fn encode_png_within(config: &Config, image: &RgbaImage, max_bytes: usize) -> anyhow::Result<Vec<u8>> {
+    let mut data = encode(image, OutputFormat::Png, config.jpeg_background, None)?;
+    let mut dropped_bits = 0;
+    while data.len() > max_bytes {
+        if dropped_bits == MAX_DROPPED_COLOR_BITS {
+            return Err(UserRecoverableError::new(format!("The sticker is too large ({} KB, at most {} KB)", data.len() / 1024, max_bytes / 1024)).into());
+        }
+        dropped_bits += 1;
+        let mut posterized = image.clone();
+        for px in posterized.pixels_mut() {
+            for channel in 0..3 {
+                px[channel] &= 0xff << dropped_bits;
+            }
+        }
+        data = encode(&posterized, OutputFormat::Png, config.jpeg_background, None)?;
+    }
+    Ok(data)
+}
+
+/// Encode the avatar resized to each of `ICO_SIZES` as the PNG entries of an icon
+fn encode_ico(avatar: &RgbaImage) -> anyhow::Result<Vec<u8>> {
+    let frames = ICO_SIZES.iter()
+        .map(|size| {
+            let resized = resize(avatar, *size, *size, FilterType::Lanczos3);
+            IcoFrame::as_png(resized.as_raw(), *size, *size, ColorType::Rgba8)
+        })
+        .collect::<ImageResult<Vec<IcoFrame>>>()?;
+
+    let mut buf: Vec<u8> = Vec::new();
+    IcoEncoder::new(&mut buf).encode_images(&frames)?;
+    Ok(buf)
+}
+
+/// The file name of an output prefixed with the user name, kept to the ASCII letters, digits, dashes and
+/// underscores that every client handles, or with the user id when none of the name is left
+pub fn user_filename(username: &str, user: UserId, filename: &str) -> String {
+    let mut name = String::new();
+    for c in username.chars() {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            name.push(c);
+        } else if c.is_whitespace() && !name.is_empty() && !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name = name.trim_matches('_');
+    if name.is_empty() {
+        format!("{}-{}", user, filename)
+    } else {
+        format!("{}-{}", name, filename)
+    }
+}
+
+/// The MIME type of an output file, from the extension of its name
+pub fn mime_type(filename: &str) -> &'static str {
+    match filename.rsplit('.').next() {
+        Some("jpg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        Some("gif") => "image/gif",
+        Some("ico") => "image/x-icon",
+        _ => "image/png",
+    }
+}
+
+/// The first attachment as a base64 `data:` URI, refused when it doesn't fit in a Discord message
+pub fn data_uri(attachments: &[AttachmentType]) -> anyhow::Result<String> {
+    let (data, filename) = match attachments.first() {
+        Some(AttachmentType::Bytes { data, filename }) => (data, filename),
+        _ => anyhow::bail!("No encoded avatar to send as a data URI"),
+    };
+    let uri = format!("data:{};base64,{}", mime_type(filename), BASE64_STANDARD.encode(data));
+    if uri.len() > MAX_MESSAGE_LENGTH {
+        return Err(UserRecoverableError::new(format!("The image is too large for a data URI ({} characters, at most {})", uri.len(), MAX_MESSAGE_LENGTH)).into());
+    }
+    Ok(uri)
+}
+
+/// Encode the avatar, with `dpi` stored in the pHYs chunk of PNG outputs.
+/// Every output is encoded from the decoded pixels, so the EXIF (like GPS positions) and other metadata of the
+/// upload never reach it; keep it that way rather than passing uploaded bytes through.
+pub fn encode(avatar: &RgbaImage, format: OutputFormat, jpeg_background: Rgb<u8>, dpi: Option<u32>) -> anyhow::Result<Vec<u8>> {
+    encode_with_quality(avatar, format, jpeg_background, dpi, JPEG_QUALITY)
+}
+
+/// Encode the avatar under the configured `max_output_bytes`, lowering the JPEG quality first and then the dimensions.
+/// Once the floors are reached the smallest encoding is returned even if it is still too large.
+pub fn encode_capped(config: &Config, avatar: &RgbaImage, format: OutputFormat, dpi: Option<u32>) -> anyhow::Result<Vec<u8>> {
+    let mut encoded = encode(avatar, format, config.jpeg_background, dpi)?;
+    let max_bytes = match config.max_output_bytes {
+        Some(max_bytes) => max_bytes,
+        None => return Ok(encoded),
+    };
+
+    let mut avatar = Cow::Borrowed(avatar);
+    let mut quality = JPEG_QUALITY;
+    while encoded.len() > max_bytes {
+        if format == OutputFormat::Jpeg && quality > MIN_JPEG_QUALITY {
+            quality -= 10;
+        } else if avatar.width() > MIN_CAPPED_SIDE {
+            let side = ((avatar.width() as f32 * 0.8) as u32).max(MIN_CAPPED_SIDE);
+            avatar = Cow::Owned(resize(&*avatar, side, side, FilterType::Lanczos3));
+        } else {
+            warn!("Cannot encode the avatar under {} bytes, sending {} bytes", max_bytes, encoded.len());
+            break;
+        }
+        encoded = encode_with_quality(&avatar, format, config.jpeg_background, dpi, quality)?;
+    }
+    Ok(encoded)
+}
+
+fn encode_with_quality(avatar: &RgbaImage, format: OutputFormat, jpeg_background: Rgb<u8>, dpi: Option<u32>, jpeg_quality: u8) -> anyhow::Result<Vec<u8>> {
+    let buf: Vec<u8> = Vec::with_capacity(avatar.as_raw().len());
+    let mut cursor: Cursor<Vec<u8>> = Cursor::new(buf);
+    match format {
+        OutputFormat::Png => match dpi {
+            Some(dpi) => return encode_png_with_dpi(avatar, dpi),
+            None => avatar.write_to(&mut cursor, ImageOutputFormat::Png)?,
+        },
+        // JPEG has no alpha channel so the transparent corners are filled with the background
+        OutputFormat::Jpeg => flatten(avatar, jpeg_background)
+            .write_to(&mut cursor, ImageOutputFormat::Jpeg(jpeg_quality))?,
+        OutputFormat::WebP => avatar.write_to(&mut cursor, ImageOutputFormat::WebP)?,
+    }
+    Ok(cursor.into_inner())
+}
+
+/// The PNG encoder of `image` cannot write the physical dimensions so use the `png` crate directly
+fn encode_png_with_dpi(avatar: &RgbaImage, dpi: u32) -> anyhow::Result<Vec<u8>> {
+    let mut buf: Vec<u8> = Vec::with_capacity(avatar.as_raw().len());
+    let mut encoder = png::Encoder::new(&mut buf, avatar.width(), avatar.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels_per_meter = (dpi as f64 / 0.0254).round() as u32;
+    encoder.set_pixel_dims(Some(png::PixelDimensions { xppu: pixels_per_meter, yppu: pixels_per_meter, unit: png::Unit::Meter }));
+    encoder.write_header()?.write_image_data(avatar.as_raw())?;
+    Ok(buf)
+}
+
+/// Blend the image over an opaque background colour
+fn flatten(image: &RgbaImage, background: Rgb<u8>) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let px = image.get_pixel(x, y);
+        let alpha = px[3] as f32 / 255.0;
+        let mut flattened = [0u8; 3];
+        for channel in 0..3 {
+            let value = px[channel] as f32 * alpha + background[channel] as f32 * (1.0 - alpha);
+            flattened[channel] = value.round() as u8;
+        }
+        Rgb(flattened)
+    })
+}
+
+fn extension(format: OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Png => "png",
+        OutputFormat::Jpeg => "jpg",
+        OutputFormat::WebP => "webp",
+    }
+}
+
+/// Decode an uploaded image, refusing images without pixels that would break the resize math
+pub fn decode(image: &[u8]) -> anyhow::Result<DynamicImage> {
+    let image = image::load_from_memory(image)?;
+    if image.width() == 0 || image.height() == 0 {
+        return Err(UserRecoverableError::new("The image is empty or invalid").into());
+    }
+    Ok(image)
+}
+
+/// The EXIF orientation tag of the image (1 being upright), photos from phones usually carry one
+fn exif_orientation(image: &[u8]) -> u32 {
+    exif::Reader::new()
+        .read_from_container(&mut Cursor::new(image))
+        .ok()
+        .and_then(|exif| exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0)))
+        .unwrap_or(1)
+}
+
+/// The embedded ICC colour profile of the image, like the Display P3 profile of photos from recent phones
+fn icc_profile(image: &[u8]) -> Option<Vec<u8>> {
+    let cursor = Cursor::new(image);
+    match image::guess_format(image).ok()? {
+        ImageFormat::Png => PngDecoder::new(cursor).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(cursor).ok()?.icc_profile(),
+        ImageFormat::WebP => WebPDecoder::new(cursor).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// Convert the colours from the ICC `profile` to sRGB, the colour space of the rings and of Discord
+fn convert_to_srgb(image: &mut RgbaImage, profile: &[u8]) -> anyhow::Result<()> {
+    let profile = Profile::new_icc(profile)?;
+    let description = profile.info(InfoType::Description, Locale::none()).unwrap_or_default();
+    if description.contains("sRGB") {
+        return Ok(());
+    }
+
+    let transform = Transform::new(&profile, PixelFormat::RGBA_8, &Profile::new_srgb(), PixelFormat::RGBA_8, Intent::Perceptual)?;
+    let mut pixels = image.pixels().map(|px| px.0).collect::<Vec<[u8; 4]>>();
+    transform.transform_in_place(&mut pixels);
+    for (px, converted) in image.pixels_mut().zip(pixels) {
+        px.0 = converted;
+    }
+    Ok(())
+}
+
+/// Rotate and flip the decoded image so that it is displayed upright
+fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// The output format matching an uploaded format, if we can encode it; anything else falls back to PNG
+fn output_format_for(format: ImageFormat) -> Option<OutputFormat> {
+    match format {
+        ImageFormat::Png => Some(OutputFormat::Png),
+        ImageFormat::Jpeg => Some(OutputFormat::Jpeg),
+        ImageFormat::WebP => Some(OutputFormat::WebP),
+        _ => None,
+    }
+}
+
+/// Check that the ring has transparency for the band and the opening to be found, through an alpha channel
+/// or the `chroma_key` colour, and that its band is at least `min_band` pixels wide
+pub fn validate_ring(ring_path: &Path, chroma_key: Option<&ChromaKey>, min_band: Option<u32>) -> anyhow::Result<()> {
+    let ring = ImageReader::open(ring_path)
+        .with_context(|| format!("Cannot open the ring {}", ring_path.display()))?
+        .decode()?;
+    if chroma_key.is_none() && !ring.color().has_alpha() {
+        anyhow::bail!("The ring {} has no alpha channel, save it with transparency or set CHAOSRING_CHROMAKEY", ring_path.display());
+    }
+
+    let mut ring = ring.to_rgba8();
+    if let Some(chroma_key) = chroma_key {
+        apply_chroma_key(&mut ring, chroma_key);
+    }
+    if !ring.pixels().any(|px| px[3] == 0) {
+        anyhow::bail!("The ring {} has no transparent pixels", ring_path.display());
+    }
+    if let Some(min_band) = min_band {
+        let band = get_ring_width(&DynamicImage::ImageRgba8(ring));
+        if band < min_band {
+            anyhow::bail!("The band of the ring {} is only {} pixels wide (at least {}), was it exported at the wrong scale?", ring_path.display(), band, min_band);
+        }
+    }
+    Ok(())
+}
+
+/// Load a ring, making the pixels of the `chroma_key` colour transparent for rings without an alpha channel
+pub fn load_ring(ring_path: &Path, chroma_key: Option<&ChromaKey>) -> anyhow::Result<RgbaImage> {
+    let mut ring = ImageReader::open(ring_path)
+        .with_context(|| format!("Cannot open the ring {}", ring_path.display()))?
+        .decode()?
+        .to_rgba8();
+
+    if let Some(chroma_key) = chroma_key {
+        apply_chroma_key(&mut ring, chroma_key);
+    }
+
+    if ring.width() != ring.height() {
+        info!("Ring {} is not square ({}x{}), padding it with transparency", ring_path.display(), ring.width(), ring.height());
+        return Ok(pad_to_square(&ring));
+    }
+
+    Ok(ring)
+}
+
+/// Decode the image of a custom emoji to use as a ring, padded to a square like the ring files
+fn emoji_ring(emoji: &[u8]) -> anyhow::Result<RgbaImage> {
+    let mut ring = decode(emoji)?.to_rgba8();
+    if ring.width() != ring.height() {
+        ring = pad_to_square(&ring);
+    }
+    // unlike the configured rings, the emoji is only checked when used
+    if find_opening(&ring).is_none() {
+        return Err(UserRecoverableError::new("Your ring emoji has no transparent centre for the avatar, pick another one").into());
+    }
+    Ok(ring)
+}
+
+pub fn apply_chroma_key(image: &mut RgbaImage, chroma_key: &ChromaKey) {
+    for px in image.pixels_mut() {
+        let matches = (0..3).all(|channel| px[channel].abs_diff(chroma_key.color[channel]) <= chroma_key.tolerance);
+        if matches {
+            *px = Rgba([0, 0, 0, 0]);
+        }
+    }
+}
+
+/// The top-left corner and side of the smallest square containing every non-transparent pixel
+pub fn opaque_bounds(image: &RgbaImage) -> Option<(u32, u32, u32)> {
+    let opaque = image.enumerate_pixels()
+        .filter(|(_, _, px)| px[3] != 0)
+        .map(|(x, y, _)| (x, y));
+    let (min_x, min_y, max_x, max_y) = opaque.fold(None, |bounds: Option<(u32, u32, u32, u32)>, (x, y)| {
+        Some(match bounds {
+            Some((min_x, min_y, max_x, max_y)) => (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)),
+            None => (x, y, x, y),
+        })
+    })?;
+
+    let side = (max_x - min_x + 1).max(max_y - min_y + 1)
+        .min(image.width() - min_x)
+        .min(image.height() - min_y);
+    Some((min_x, min_y, side))
+}
+
+/// Pad an almost square image rather than letting the resize crop it, `tolerance` being the accepted
+/// difference between the sides relative to the shorter one
+fn pad_near_square(image: RgbaImage, tolerance: f32) -> RgbaImage {
+    let (long, short) = (image.width().max(image.height()), image.width().min(image.height()));
+    if long != short && (long - short) as f32 / short as f32 <= tolerance {
+        return pad_to_square(&image);
+    }
+    image
+}
+
+/// Center the image on a transparent square canvas with the side of its larger dimension
+fn pad_to_square(image: &RgbaImage) -> RgbaImage {
+    let side = image.width().max(image.height());
+    let mut buffer = RgbaImage::new(side, side);
+    overlay(&mut buffer, image, ((side - image.width()) / 2) as i64, ((side - image.height()) / 2) as i64);
+    buffer
+}
+
+/// The tier forced for development or a random one in demo mode if set, otherwise the tier of the roles
+pub fn resolve_tier<'c>(config: &'c Config, user_roles: &[RoleId]) -> anyhow::Result<&'c Tier> {
+    if let Some(name) = &config.force_tier {
+        return config.tiers.iter()
+            .find(|tier| tier.name.eq_ignore_ascii_case(name))
+            .with_context(|| format!("No tier named {}", name));
+    }
+    if config.random_tier && !config.tiers.is_empty() {
+        // the hasher keys are random, which is enough for a demo without a random number crate
+        let index = RandomState::new().build_hasher().finish() as usize % config.tiers.len();
+        let tier = &config.tiers[index];
+        info!("Demo mode: picked the {} tier at random", tier.name);
+        return Ok(tier);
+    }
+    find_dao_role(&config.tiers, user_roles, config.role_help.as_deref())
+}
+
+/// The first of the configured tiers the member holds a role for, the error of roleless members
+/// telling them how to get a role when `role_help` is set
+pub fn find_dao_role<'c>(tiers: &'c [Tier], user_roles: &[RoleId], role_help: Option<&str>) -> anyhow::Result<&'c Tier> {
+    tiers.iter()
+        .find(|tier| tier.role_ids().any(|role_id| user_roles.contains(&role_id)))
+        .ok_or_else(|| {
+            let names = tiers.iter().map(|tier| tier.name.as_str()).collect::<Vec<&str>>().join(", ");
+            let inner = UserRecoverableError::new(format!("User does not hold any of the ring roles ({})", names))
+                .with_hint(role_help);
+            anyhow::Error::new(inner)
+        })
+}
+
+/// Composite the ring over the avatar, or under it with `ring_behind` so the avatar overlaps the inner edge of the ring
+fn overlay_ring(config: &Config, avatar: &RgbaImage, ring: &RgbaImage, options: &RingOptions) -> ImageResult<ImageBuffer<Rgba<u8>, Vec<u8>>> {
+    debug!("Dimensions: avatar @ {:?}, ring @ {:?}", avatar.dimensions(), ring.dimensions());
+
+    let mut ring = DynamicImage::ImageRgba8(ring.clone());
+    let avatar = DynamicImage::ImageRgba8(avatar.clone());
+    // images must be square so one dimension is enough
+    let avatar_side = avatar.width();
+    let original_ring_side = ring.width();
+    // the output is no larger than the avatar:
+    // - a larger ring is shrunk to the avatar, unless `keep_ring_size` keeps its resolution, the avatar being upscaled
+    // - a ring of the same side is used as is, its opening being smaller than the avatar which is shrunk into it
+    // - a smaller ring is used as is too, the avatar being shrunk into its opening the same way
+    if original_ring_side > avatar_side && !options.keep_ring_size {
+        ring = ring.resize_to_fill(avatar_side, avatar_side, config.ring_filter);
+    }
+    let ring_side = ring.width();
+    let (opening_x, opening_y, opening_width, opening_height) = find_opening(&ring.to_rgba8())
+        .unwrap_or_else(|| {
+            let band = get_ring_width(&ring);
+            (band, band, ring_side - 2 * band, ring_side - 2 * band)
+        });
+    let (opening_x, opening_y, opening_width, opening_height) = match config.avatar_size {
+        Some(size) => {
+            // centered in the opening, and no larger than it
+            let side = size.min(opening_width).min(opening_height);
+            (opening_x + (opening_width - side) / 2, opening_y + (opening_height - side) / 2, side, side)
+        }
+        None => (opening_x, opening_y, opening_width, opening_height),
+    };
+    let mut scaled_avatar = avatar.resize_to_fill(opening_width, opening_height, FilterType::Nearest)
+        .to_rgba8();
+    if options.sharpen {
+        scaled_avatar = unsharpen(&scaled_avatar, config.sharpen.sigma, config.sharpen.threshold);
+    }
+
+    // the band is measured first so that a faint ring still frames the avatar the same way
+    let mut ring = ring.to_rgba8();
+    if let Some(opacity) = options.ring_opacity {
+        for px in ring.pixels_mut() {
+            px[3] = (px[3] as u32 * opacity as u32 / 100) as u8;
+        }
+    }
+    if let Some(tint) = options.tint {
+        for px in ring.pixels_mut() {
+            for channel in 0..3 {
+                px[channel] = (px[channel] as u32 * tint[channel] as u32 / 255) as u8;
+            }
+        }
+    }
+    if let Some(progress) = options.progress {
+        keep_arc(&mut ring, progress);
+        // the corners of the avatar would show where the ring is cleared
+        let radius = opening_width.min(opening_height) / 2;
+        apply_mask(&mut scaled_avatar, radius, (opening_width / 2) as f32, (opening_height / 2) as f32, &Mask::default());
+    }
+    if config.mask.vignette > 0.0 {
+        // only the avatar is darkened, not the ring over it
+        let radius = opening_width.min(opening_height) / 2;
+        let vignette = Mask { clear_outer: false, ..config.mask };
+        apply_mask(&mut scaled_avatar, radius, (opening_width / 2) as f32, (opening_height / 2) as f32, &vignette);
+    }
+
+    // without a background image the buffer starts fully transparent and `replace` sets pixels rather
+    // than blending them, so an avatar that is already cut out (transparent corners) keeps its own alpha
+    // the padding keeps the anti-aliased pixels at the very edge of the ring out of reach of the circular mask
+    let padding = config.canvas_padding;
+    let canvas_side = ring_side + 2 * padding;
+    let mut buffer = match &config.background_image {
+        Some(background) => background
+            .resize_to_fill(canvas_side, canvas_side, config.ring_filter)
+            .to_rgba8(),
+        None => RgbaImage::new(canvas_side, canvas_side),
+    };
+    // centered on the canvas, then moved by the offset given in pixels of the ring image, the avatar
+    // following the opening
+    let (offset_x, offset_y) = config.ring_offset;
+    let scale_offset = |offset: i32| offset as i64 * ring_side as i64 / original_ring_side as i64;
+    let ring_x = (buffer.width() as i64 - ring_side as i64) / 2 + scale_offset(offset_x);
+    let ring_y = (buffer.height() as i64 - ring_side as i64) / 2 + scale_offset(offset_y);
+    let (opening_x, opening_y) = (ring_x + opening_x as i64, ring_y + opening_y as i64);
+    if options.ring_behind {
+        overlay_premultiplied(&mut buffer, &ring, ring_x, ring_y, BlendMode::Normal);
+        overlay_premultiplied(&mut buffer, &scaled_avatar, opening_x, opening_y, BlendMode::Normal);
+    } else {
+        if config.background_image.is_some() {
+            overlay_premultiplied(&mut buffer, &scaled_avatar, opening_x, opening_y, BlendMode::Normal);
+        } else {
+            replace(&mut buffer, &scaled_avatar, opening_x, opening_y);
+        }
+        overlay_premultiplied(&mut buffer, &ring, ring_x, ring_y, options.blend_mode.unwrap_or(config.blend_mode));
+    }
+    let cx = (buffer.width() / 2) as f32;
+    let cy = (buffer.height() / 2) as f32;
+    let radius = buffer.width() / 2;
+    apply_mask(&mut buffer, radius, cx, cy, &Mask { vignette: 0.0, ..config.mask });
+
+    Ok(buffer)
+}
+
+/// Clear the pixels past the arc going clockwise from the top over `progress` percent of the circle
+fn keep_arc(image: &mut RgbaImage, progress: u8) {
+    let end = progress as f32 / 100.0 * TAU;
+    let cx = image.width() as f32 / 2.0;
+    let cy = image.height() as f32 / 2.0;
+    for (x, y, px) in image.enumerate_pixels_mut() {
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        let angle = dx.atan2(-dy).rem_euclid(TAU);
+        if angle > end {
+            px[3] = 0;
+        }
+    }
+}
+
+/// The bounding box (x, y, width, height) of the transparent opening of the ring: the largest transparent area
+/// enclosed by the ring, wherever it is, the transparency touching the edges of the image being outside the ring.
+/// None when there is no enclosed area, the opening not being closed, for which the band width is a better guess.
+pub fn find_opening(ring: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+    let (width, height) = ring.dimensions();
+    let mut visited = vec![false; (width as usize) * (height as usize)];
+    // pixel count and bounding box of the largest enclosed area found so far
+    let mut opening: Option<(usize, (u32, u32, u32, u32))> = None;
+    for (start_x, start_y, px) in ring.enumerate_pixels() {
+        let start = (start_y * width + start_x) as usize;
+        if visited[start] || px[3] != 0 {
+            continue;
+        }
+
+        // flood fill the transparent pixels connected to this one
+        visited[start] = true;
+        let mut pending = vec![(start_x, start_y)];
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (start_x, start_y, start_x, start_y);
+        let (mut count, mut enclosed) = (0, true);
+        while let Some((x, y)) = pending.pop() {
+            count += 1;
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+            if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+                enclosed = false;
+            }
+            let neighbours = [(x.checked_sub(1), Some(y)), (Some(x + 1), Some(y)), (Some(x), y.checked_sub(1)), (Some(x), Some(y + 1))];
+            for (nx, ny) in neighbours.into_iter().filter_map(|(nx, ny)| nx.zip(ny)) {
+                if nx >= width || ny >= height {
+                    continue;
+                }
+                let index = (ny * width + nx) as usize;
+                if !visited[index] && ring.get_pixel(nx, ny)[3] == 0 {
+                    visited[index] = true;
+                    pending.push((nx, ny));
+                }
+            }
+        }
+
+        if enclosed && opening.is_none_or(|(largest, _)| count > largest) {
+            opening = Some((count, (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1)));
+        }
+    }
+    opening.map(|(_, bounds)| bounds)
+}
+
+/// Composite `top` over `bottom` in premultiplied alpha so the semi-transparent (anti-aliased) edges
+/// of the ring don't pick up the colour of transparent pixels underneath and form a dark halo
+fn overlay_premultiplied(bottom: &mut RgbaImage, top: &RgbaImage, x: i64, y: i64, mode: BlendMode) {
+    for (top_x, top_y, top_px) in top.enumerate_pixels() {
+        let (bottom_x, bottom_y) = (x + top_x as i64, y + top_y as i64);
+        if bottom_x < 0 || bottom_y < 0 || bottom_x >= bottom.width() as i64 || bottom_y >= bottom.height() as i64 {
+            continue;
+        }
+        let bottom_px = bottom.get_pixel_mut(bottom_x as u32, bottom_y as u32);
+        *bottom_px = blend_premultiplied(bottom_px, top_px, mode);
+    }
+}
+
+fn blend_premultiplied(bottom: &Rgba<u8>, top: &Rgba<u8>, mode: BlendMode) -> Rgba<u8> {
+    let top_alpha = top[3] as f32 / 255.0;
+    let bottom_alpha = bottom[3] as f32 / 255.0;
+    let alpha = top_alpha + bottom_alpha * (1.0 - top_alpha);
+    if alpha == 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+
+    let mut blended = [0u8; 4];
+    for channel in 0..3 {
+        // where the bottom is opaque the top colour is replaced by its blend with the bottom one
+        let (top_color, bottom_color) = (top[channel] as f32, bottom[channel] as f32);
+        let mixed = match mode {
+            BlendMode::Normal => top_color,
+            BlendMode::Screen => 255.0 - (255.0 - top_color) * (255.0 - bottom_color) / 255.0,
+            BlendMode::Add => (top_color + bottom_color).min(255.0),
+        };
+        let top_color = top_color * (1.0 - bottom_alpha) + mixed * bottom_alpha;
+        let top_premultiplied = top_color * top_alpha;
+        let bottom_premultiplied = bottom[channel] as f32 * bottom_alpha;
+        let premultiplied = top_premultiplied + bottom_premultiplied * (1.0 - top_alpha);
+        blended[channel] = (premultiplied / alpha).round().clamp(0.0, 255.0) as u8;
+    }
+    blended[3] = (alpha * 255.0).round() as u8;
+    Rgba(blended)
+}
+
+/// Draw the image over a blurred dark copy of its own shape, on a canvas enlarged to fit the shadow
+fn add_shadow(image: &RgbaImage, shadow: &Shadow) -> RgbaImage {
+    // a gaussian blur fades out within three standard deviations
+    let spread = (shadow.blur * 3.0).ceil() as u32;
+    let side = image.width().max(image.height()) + 2 * spread + shadow.offset;
+
+    let mut silhouette = RgbaImage::new(side, side);
+    for (x, y, px) in image.enumerate_pixels() {
+        let alpha = (px[3] as f32 * shadow.opacity).round() as u8;
+        silhouette.put_pixel(x + spread + shadow.offset, y + spread + shadow.offset, Rgba([0, 0, 0, alpha]));
+    }
+    let mut buffer = if shadow.blur > 0.0 { blur(&silhouette, shadow.blur) } else { silhouette };
+
+    overlay_premultiplied(&mut buffer, image, spread as i64, spread as i64, BlendMode::Normal);
+    buffer
+}
+
+/// Mask of the circle of `radius` centred on (`cx`, `cy`): the outside cleared, with a hard or feathered edge,
+/// and the inside darkened towards the edge by the vignette
+pub fn apply_mask(buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, radius: u32, cx: f32, cy: f32, mask: &Mask) {
+    let radius = radius as f32;
+    buffer.enumerate_pixels_mut()
+        .for_each(|(x, y, px)| {
+            let distance = (x as f32 - cx).hypot(y as f32 - cy);
+            if mask.clear_outer {
+                let coverage = if mask.feather > 0.0 {
+                    ((radius - distance) / mask.feather).clamp(0.0, 1.0)
+                } else if distance > radius {
+                    0.0
+                } else {
+                    1.0
+                };
+                px[3] = (px[3] as f32 * coverage).round() as u8;
+            }
+            if mask.vignette > 0.0 && distance < radius {
+                let darkening = 1.0 - mask.vignette * (distance / radius).powi(2);
+                for channel in 0..3 {
+                    px[channel] = (px[channel] as f32 * darkening).round() as u8;
+                }
+            }
+        });
+}
+
+pub fn get_ring_width(ring_img: &DynamicImage) -> u32 {
+    // count non-transparent pixels along the top half the Y axis (in a single column)
+    let x = ring_img.width() / 2;
+    (0..ring_img.height() / 2)
+        .map(|y| ring_img.get_pixel(x, y))
+        .map(|pixel| if pixel[3] != 0 { 1u32 } else { 0u32 })// 1 for non-transparent pixel
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A ring of the given side, opaque outside the circle of its opening and transparent inside
+    fn ring_image(side: u32, band: u32) -> RgbaImage {
+        let center = side as f32 / 2.0;
+        let radius = center - band as f32;
+        RgbaImage::from_fn(side, side, |x, y| {
+            let distance = (x as f32 + 0.5 - center).hypot(y as f32 + 0.5 - center);
+            if distance < radius { Rgba([0, 0, 0, 0]) } else { Rgba([200, 30, 30, 255]) }
+        })
+    }
+
+    /// A square opaque disc, transparent around it like a ring, with a round opening of `radius` at `center`
+    fn disc_with_opening(side: u32, center: (f32, f32), radius: f32) -> RgbaImage {
+        let middle = side as f32 / 2.0;
+        RgbaImage::from_fn(side, side, |x, y| {
+            let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+            let outside = (px - middle).hypot(py - middle) > middle;
+            let opening = (px - center.0).hypot(py - center.1) < radius;
+            if outside || opening { Rgba([0, 0, 0, 0]) } else { Rgba([200, 30, 30, 255]) }
+        })
+    }
+
+    /// A config without tiers, every setting left to its default
+    fn default_config() -> Config {
+        toml::from_str("tiers = []").unwrap()
+    }
+
+    /// A config with a single tier for role 1, its ring saved under a name unique to the test
+    fn test_config(name: &str, ring: &RgbaImage) -> Config {
+        let path = std::env::temp_dir().join(format!("chaosring-{}-{}.png", name, std::process::id()));
+        ring.save(&path).unwrap();
+        let mut config = default_config();
+        config.tiers.push(Tier {
+            name: String::from("DAOist"),
+            role_ids: vec![1],
+            ring: path,
+            scale: None,
+            format: None,
+            label_color: None,
+            label_font: None,
+            variants: Default::default(),
+        });
+        config
+    }
+
+    fn png(image: &RgbaImage) -> Vec<u8> {
+        encode(image, OutputFormat::Png, Rgb([255, 255, 255]), None).unwrap()
+    }
+
+    fn dimensions(attachment: &AttachmentType) -> (u32, u32) {
+        match attachment {
+            AttachmentType::Bytes { data, .. } => decode(data).unwrap().dimensions(),
+            _ => panic!("expected an attachment in memory"),
+        }
+    }
+
+    #[test]
+    fn preview_is_small_and_replaced_by_the_full_avatar() {
+        let config = test_config("preview", &ring_image(256, 32));
+        let avatar = png(&RgbaImage::from_pixel(1024, 768, Rgba([10, 120, 240, 255])));
+        let options = RingOptions::default();
+
+        let pending = PendingRing::new(&config, &[RoleId(1)], &avatar, &options).unwrap();
+        let preview = pending.preview(&config, &options).unwrap();
+        let ringed = pending.ring(&config, &options).unwrap();
+
+        assert_eq!(dimensions(&preview), (PREVIEW_SIDE, PREVIEW_SIDE));
+        assert_eq!(ringed.tier.as_deref(), Some("DAOist"));
+        assert_eq!(dimensions(&ringed.attachments[0]), (256, 256));
+    }
+
+    #[test]
+    fn finds_the_centred_opening() {
+        assert_eq!(find_opening(&ring_image(64, 8)), Some((8, 8, 48, 48)));
+    }
+
+    #[test]
+    fn finds_an_off_centre_opening() {
+        let mut ring = disc_with_opening(64, (22.0, 26.0), 10.0);
+        // a smaller hole elsewhere in the band is not the opening
+        for (x, y) in [(48, 40), (49, 40), (48, 41), (49, 41)] {
+            ring.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+        }
+        assert_eq!(find_opening(&ring), Some((12, 16, 20, 20)));
+    }
+
+    #[test]
+    fn an_opening_reaching_the_edge_is_not_found() {
+        let mut ring = ring_image(64, 8);
+        for x in 32..64 {
+            ring.put_pixel(x, 32, Rgba([0, 0, 0, 0]));
+        }
+        assert_eq!(find_opening(&ring), None);
+    }
+
+    /// Overlay the ring of the given side on a plain avatar of the given side, returning the output side and
+    /// the colour in the middle of the band, on the left of the output
+    fn overlay_sides(ring_side: u32, avatar_side: u32, options: &RingOptions) -> (u32, Rgba<u8>) {
+        let ring = ring_image(ring_side, ring_side / 8);
+        let avatar = RgbaImage::from_pixel(avatar_side, avatar_side, Rgba([10, 120, 240, 255]));
+        let output = overlay_ring(&default_config(), &avatar, &ring, options).unwrap();
+        assert_eq!(output.width(), output.height());
+        let band = *output.get_pixel(output.width() / 16, output.height() / 2);
+        (output.width(), band)
+    }
+
+    #[test]
+    fn a_larger_ring_is_shrunk_to_the_avatar() {
+        assert_eq!(overlay_sides(128, 64, &RingOptions::default()), (64, Rgba([200, 30, 30, 255])));
+        let keep_ring_size = RingOptions { keep_ring_size: true, ..RingOptions::default() };
+        assert_eq!(overlay_sides(128, 64, &keep_ring_size).0, 128);
+    }
+
+    #[test]
+    fn a_ring_of_the_avatar_side_is_kept_as_is() {
+        assert_eq!(overlay_sides(64, 64, &RingOptions::default()), (64, Rgba([200, 30, 30, 255])));
+    }
+
+    #[test]
+    fn a_smaller_ring_keeps_its_resolution() {
+        assert_eq!(overlay_sides(64, 128, &RingOptions::default()), (64, Rgba([200, 30, 30, 255])));
+    }
+
+    #[test]
+    fn the_output_grows_steadily_around_the_avatar_side() {
+        let sides = (62..=66).map(|ring_side| overlay_sides(ring_side, 64, &RingOptions::default()).0).collect::<Vec<u32>>();
+        assert_eq!(sides, vec![62, 63, 64, 64, 64]);
+    }
+
+    #[test]
+    fn blending_over_transparency_keeps_the_colour_of_edge_pixels() {
+        let transparent = Rgba([0, 0, 0, 0]);
+        // an anti-aliased edge pixel of the ring would get a dark fringe without premultiplication
+        assert_eq!(blend_premultiplied(&transparent, &Rgba([200, 30, 30, 128]), BlendMode::Normal), Rgba([200, 30, 30, 128]));
+        assert_eq!(blend_premultiplied(&Rgba([200, 30, 30, 128]), &transparent, BlendMode::Normal), Rgba([200, 30, 30, 128]));
+        assert_eq!(blend_premultiplied(&transparent, &transparent, BlendMode::Normal), transparent);
+    }
+
+    #[test]
+    fn blends_half_transparent_pixels_over_opaque_ones() {
+        let black = Rgba([0, 0, 0, 255]);
+        assert_eq!(blend_premultiplied(&black, &Rgba([255, 255, 255, 255]), BlendMode::Normal), Rgba([255, 255, 255, 255]));
+        assert_eq!(blend_premultiplied(&black, &Rgba([255, 255, 255, 128]), BlendMode::Normal), Rgba([128, 128, 128, 255]));
+    }
+
+    #[test]
+    fn a_non_square_ring_is_padded_to_a_square() {
+        let mut config = test_config("non-square", &RgbaImage::from_pixel(64, 48, Rgba([200, 30, 30, 255])));
+        let ring = load_ring(&config.tiers.remove(0).ring, None).unwrap();
+        assert_eq!(ring.dimensions(), (64, 64));
+        // centred, with transparent bands above and below
+        assert_eq!(ring.get_pixel(32, 7)[3], 0);
+        assert_eq!(ring.get_pixel(32, 8)[3], 255);
+        assert_eq!(ring.get_pixel(32, 55)[3], 255);
+        assert_eq!(ring.get_pixel(32, 56)[3], 0);
+    }
+
+    /// Whether each channel of the pixel is within `tolerance` of the colour, for lossy outputs
+    fn is_close(px: Rgba<u8>, color: [u8; 3], tolerance: u8) -> bool {
+        (0..3).all(|channel| px[channel].abs_diff(color[channel]) <= tolerance)
+    }
+
+    #[test]
+    fn jpeg_outputs_fill_the_transparent_corners_with_the_background() {
+        let mut avatar = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255]));
+        apply_mask(&mut avatar, 32, 32.0, 32.0, &Mask::default());
+        let jpeg = encode(&avatar, OutputFormat::Jpeg, Rgb([0x36, 0x39, 0x3f]), None).unwrap();
+
+        let decoded = decode(&jpeg).unwrap().to_rgba8();
+        for (x, y) in [(0, 0), (63, 0), (0, 63), (63, 63)] {
+            assert!(is_close(*decoded.get_pixel(x, y), [0x36, 0x39, 0x3f], 4), "corner {:?}", decoded.get_pixel(x, y));
+        }
+        assert!(is_close(*decoded.get_pixel(32, 32), [10, 120, 240], 4));
+    }
+
+    #[test]
+    fn maps_the_uploaded_formats_to_the_output_formats() {
+        assert_eq!(output_format_for(ImageFormat::Png), Some(OutputFormat::Png));
+        assert_eq!(output_format_for(ImageFormat::Jpeg), Some(OutputFormat::Jpeg));
+        assert_eq!(output_format_for(ImageFormat::WebP), Some(OutputFormat::WebP));
+        assert_eq!(output_format_for(ImageFormat::Gif), None);
+        assert_eq!(output_format_for(ImageFormat::Bmp), None);
+    }
+
+    #[test]
+    fn preserves_the_uploaded_format_when_enabled() {
+        let mut config = test_config("preserve-format", &ring_image(64, 8));
+        config.preserve_format = true;
+        let tier = config.tiers[0].clone();
+        let avatar = DynamicImage::ImageRgba8(RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255])));
+        let uploads = [
+            (ImageOutputFormat::Png, "avatar.png"),
+            (ImageOutputFormat::Jpeg(90), "avatar.jpg"),
+            (ImageOutputFormat::WebP, "avatar.webp"),
+            // GIF outputs would lose the semi-transparent edges
+            (ImageOutputFormat::Gif, "avatar.png"),
+        ];
+        for (format, expected) in uploads {
+            let mut upload = Cursor::new(Vec::new());
+            avatar.write_to(&mut upload, format).unwrap();
+            let attachments = apply_tier_ring(&config, &tier, upload.get_ref(), &RingOptions::default()).unwrap();
+            match &attachments[0] {
+                AttachmentType::Bytes { filename, .. } => assert_eq!(filename, expected),
+                _ => panic!("expected an attachment in memory"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn rings_a_canned_avatar_without_downloading_it() {
+        let config = test_config("run", &ring_image(64, 8));
+        let avatar = png(&RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255])));
+
+        let ringed = run(&config, &[RoleId(1)], &avatar, &RingOptions::default()).await.unwrap();
+        assert_eq!(ringed.tier.as_deref(), Some("DAOist"));
+        assert_eq!(dimensions(&ringed.attachments[0]), (64, 64));
+
+        let err = run(&config, &[RoleId(2)], &avatar, &RingOptions::default()).await.err().unwrap();
+        assert!(err.downcast_ref::<UserRecoverableError>().is_some());
+    }
+
+    #[test]
+    fn the_ring_filter_shapes_the_shrunk_ring() {
+        // a band of one-pixel stripes, which nearest-neighbour sampling keeps hard while Lanczos blends them
+        let mut ring = ring_image(128, 16);
+        for (x, _, px) in ring.enumerate_pixels_mut().filter(|(_, _, px)| px[3] != 0) {
+            if x % 2 == 0 {
+                *px = Rgba([255, 255, 255, 255]);
+            }
+        }
+        let avatar = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255]));
+        let overlay_with = |ring_filter: FilterType| {
+            let config = Config { ring_filter, ..default_config() };
+            overlay_ring(&config, &avatar, &ring, &RingOptions::default()).unwrap()
+        };
+        assert_ne!(overlay_with(FilterType::Lanczos3), overlay_with(FilterType::Nearest));
+    }
+
+    /// A ring of the given side with a square opening of `opening` pixels in its middle, so that the corners of
+    /// the avatar inside it are not covered by the band
+    fn square_opening_ring(side: u32, opening: u32) -> RgbaImage {
+        let start = (side - opening) / 2;
+        let inside = |value: u32| (start..start + opening).contains(&value);
+        RgbaImage::from_fn(side, side, |x, y| {
+            if inside(x) && inside(y) { Rgba([0, 0, 0, 0]) } else { Rgba([200, 30, 30, 255]) }
+        })
+    }
+
+    #[test]
+    fn the_transparent_corners_of_a_cut_out_avatar_are_kept() {
+        let mut avatar = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255]));
+        apply_mask(&mut avatar, 32, 32.0, 32.0, &Mask::default());
+        assert_eq!(avatar.get_pixel(0, 0)[3], 0);
+
+        let output = overlay_ring(&default_config(), &avatar, &square_opening_ring(64, 32), &RingOptions::default()).unwrap();
+        assert_eq!(*output.get_pixel(16, 16), Rgba([0, 0, 0, 0]));
+        assert_eq!(*output.get_pixel(47, 47), Rgba([0, 0, 0, 0]));
+        assert_eq!(*output.get_pixel(32, 32), Rgba([10, 120, 240, 255]));
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_sizes(" 64, 128,64 ,", 4096).unwrap(), vec![64, 128]);
+        assert!(parse_sizes("0", 4096).is_err());
+        assert!(parse_sizes("large", 4096).is_err());
+        assert!(parse_sizes("4097", 4096).is_err());
+    }
+
+    #[test]
+    fn two_sizes_give_two_attachments() {
+        let config = test_config("sizes", &ring_image(128, 16));
+        let avatar = png(&RgbaImage::from_pixel(128, 128, Rgba([10, 120, 240, 255])));
+        let options = RingOptions { sizes: vec![64, 32], ..RingOptions::default() };
+
+        let ringed = process_avatar(&config, &[RoleId(1)], &avatar, &options).unwrap();
+        assert_eq!(ringed.attachments.len(), 2);
+        assert_eq!(dimensions(&ringed.attachments[0]), (64, 64));
+        assert_eq!(dimensions(&ringed.attachments[1]), (32, 32));
+        match &ringed.attachments[1] {
+            AttachmentType::Bytes { filename, .. } => assert_eq!(filename, "avatar-32.png"),
+            _ => panic!("expected an attachment in memory"),
+        }
+    }
+
+    #[test]
+    fn ring_behind_draws_the_avatar_over_the_inner_edge_of_the_ring() {
+        let (ring, avatar) = (ring_image(64, 8), RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255])));
+        // in the corner of the square of the avatar, which the band covers
+        let corner = |options: &RingOptions| *overlay_ring(&default_config(), &avatar, &ring, options).unwrap().get_pixel(10, 10);
+        assert_eq!(corner(&RingOptions::default()), Rgba([200, 30, 30, 255]));
+        assert_eq!(corner(&RingOptions { ring_behind: true, ..RingOptions::default() }), Rgba([10, 120, 240, 255]));
+    }
+
+    /// The JPEG with an APP1 segment holding the EXIF `tiff` structure, right after the start of image marker
+    fn with_exif(jpeg: &[u8], tiff: &[u8]) -> Vec<u8> {
+        let payload = [b"Exif\0\0".as_slice(), tiff].concat();
+        let length = (payload.len() + 2) as u16;
+        [&jpeg[..2], &[0xff, 0xe1], &length.to_be_bytes(), &payload, &jpeg[2..]].concat()
+    }
+
+    #[test]
+    fn photos_are_turned_upright_from_their_exif_orientation() {
+        // stored sideways, the left half being the top of the picture
+        let stored = RgbaImage::from_fn(32, 16, |x, _| if x < 16 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) });
+        let jpeg = encode(&stored, OutputFormat::Jpeg, Rgb([0, 0, 0]), None).unwrap();
+        // big-endian TIFF header then a single IFD entry: orientation (0x0112), SHORT, 1 value, 6 (rotate 90°)
+        let tiff = [
+            b"MM\0\x2a\0\0\0\x08".as_slice(),
+            &[0, 1], &[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0], &[0, 0, 0, 0],
+        ].concat();
+
+        let (avatar, _) = prepare_avatar(&default_config(), &with_exif(&jpeg, &tiff), &RingOptions::default()).unwrap();
+        assert_eq!(avatar.dimensions(), (16, 32));
+        assert!(is_close(*avatar.get_pixel(8, 4), [255, 0, 0], 16));
+        assert!(is_close(*avatar.get_pixel(8, 28), [0, 0, 255], 16));
+    }
+
+    #[test]
+    fn the_dpi_is_written_in_the_phys_chunk() {
+        let avatar = RgbaImage::from_pixel(16, 16, Rgba([10, 120, 240, 255]));
+        let encoded = encode(&avatar, OutputFormat::Png, Rgb([0, 0, 0]), Some(300)).unwrap();
+        let reader = png::Decoder::new(Cursor::new(encoded)).read_info().unwrap();
+        let dims = reader.info().pixel_dims.unwrap();
+        // 300 dots per inch are 11811 per meter
+        assert_eq!((dims.xppu, dims.yppu, dims.unit), (11811, 11811, png::Unit::Meter));
+
+        let encoded = encode(&avatar, OutputFormat::Png, Rgb([0, 0, 0]), None).unwrap();
+        assert!(png::Decoder::new(Cursor::new(encoded)).read_info().unwrap().info().pixel_dims.is_none());
+    }
+
+    #[test]
+    fn the_self_test_reports_each_tier() {
+        let mut config = test_config("self-test", &ring_image(64, 8));
+        let mut broken = config.tiers[0].clone();
+        broken.name = String::from("Broken");
+        broken.ring = std::env::temp_dir().join("chaosring-missing-ring.png");
+        config.tiers.push(broken);
+
+        let outcomes = self_test_outcomes(&config).unwrap();
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0].0, "DAOist");
+        assert!(outcomes[0].1.is_ok());
+        assert_eq!(outcomes[1].0, "Broken");
+        assert!(outcomes[1].1.as_ref().unwrap_err().to_string().contains("Cannot open the ring"));
+    }
+
+    /// Pseudo-random pixels, which compress poorly
+    fn noise(side: u32) -> RgbaImage {
+        let mut state = 12345u32;
+        RgbaImage::from_fn(side, side, |_, _| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let [r, g, b, _] = state.to_le_bytes();
+            Rgba([r, g, b, 255])
+        })
+    }
+
+    #[test]
+    fn a_large_output_is_brought_under_the_cap() {
+        let mut config = default_config();
+        let avatar = noise(512);
+        assert!(encode(&avatar, OutputFormat::Png, config.jpeg_background, None).unwrap().len() > 500_000);
+
+        config.max_output_bytes = Some(100_000);
+        let png = encode_capped(&config, &avatar, OutputFormat::Png, None).unwrap();
+        assert!(png.len() <= 100_000, "{} bytes", png.len());
+        let jpeg = encode_capped(&config, &avatar, OutputFormat::Jpeg, None).unwrap();
+        assert!(jpeg.len() <= 100_000, "{} bytes", jpeg.len());
+    }
+
+    #[test]
+    fn the_shadow_enlarges_the_canvas_with_semi_transparent_pixels() {
+        let mut avatar = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255]));
+        apply_mask(&mut avatar, 32, 32.0, 32.0, &Mask::default());
+        let shadow = Shadow::default();
+        let shadowed = add_shadow(&avatar, &shadow);
+
+        let spread = (shadow.blur * 3.0).ceil() as u32;
+        assert_eq!(shadowed.dimensions(), (64 + 2 * spread + shadow.offset, 64 + 2 * spread + shadow.offset));
+        // the avatar is drawn over its shadow, which shows past its bottom right edge
+        assert_eq!(*shadowed.get_pixel(spread + 32, spread + 32), Rgba([10, 120, 240, 255]));
+        let below = shadowed.get_pixel(spread + 32, spread + 64 + shadow.offset / 2);
+        assert_eq!(&below.0[..3], &[0, 0, 0]);
+        assert!(below[3] > 0 && below[3] < 255, "{:?}", below);
+    }
+
+    #[test]
+    fn an_image_without_pixels_is_refused() {
+        // a farbfeld header of 0x0, which decodes to an empty image
+        let mut empty = b"farbfeld".to_vec();
+        empty.extend_from_slice(&[0; 8]);
+        let err = decode(&empty).unwrap_err();
+        assert!(err.downcast_ref::<UserRecoverableError>().is_some(), "{:#}", err);
+    }
+
+    fn image_of(attachment: &AttachmentType) -> RgbaImage {
+        match attachment {
+            AttachmentType::Bytes { data, .. } => decode(data).unwrap().to_rgba8(),
+            _ => panic!("expected an attachment in memory"),
+        }
+    }
+
+    #[test]
+    fn no_ring_only_crops_the_avatar_to_a_circle() {
+        let avatar = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255]));
+        let options = RingOptions { no_ring: true, ..RingOptions::default() };
+        // no tier and no role are needed
+        let ringed = process_avatar(&default_config(), &[], &png(&avatar), &options).unwrap();
+        assert!(ringed.tier.is_none());
+
+        let output = image_of(&ringed.attachments[0]);
+        assert_eq!(output.dimensions(), (64, 64));
+        for (x, y) in [(0, 0), (63, 0), (0, 63), (63, 63)] {
+            assert_eq!(output.get_pixel(x, y)[3], 0);
+        }
+        // the avatar reaches the edge of the circle where a ring would be
+        for (x, y) in [(32, 32), (2, 32), (32, 2), (61, 32)] {
+            assert_eq!(*output.get_pixel(x, y), Rgba([10, 120, 240, 255]), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn an_almost_square_image_is_padded() {
+        let image = RgbaImage::from_pixel(100, 104, Rgba([10, 120, 240, 255]));
+        let padded = pad_near_square(image, 0.05);
+        assert_eq!(padded.dimensions(), (104, 104));
+        // the whole image is kept, between transparent columns
+        assert_eq!(padded.get_pixel(1, 52)[3], 0);
+        assert_eq!(*padded.get_pixel(2, 52), Rgba([10, 120, 240, 255]));
+        assert_eq!(*padded.get_pixel(101, 52), Rgba([10, 120, 240, 255]));
+        assert_eq!(padded.get_pixel(102, 52)[3], 0);
+    }
+
+    #[test]
+    fn an_image_beyond_the_tolerance_is_left_for_the_crop() {
+        let image = RgbaImage::from_pixel(100, 110, Rgba([10, 120, 240, 255]));
+        assert_eq!(pad_near_square(image, 0.05).dimensions(), (100, 110));
+    }
+
+    #[test]
+    fn flipping_twice_gives_back_the_avatar() {
+        let config = default_config();
+        let avatar = RgbaImage::from_fn(48, 48, |x, y| Rgba([(x * 5) as u8, (y * 5) as u8, 128, 255]));
+        let options = RingOptions { flip_h: true, flip_v: true, ..RingOptions::default() };
+
+        let (flipped, _) = prepare_avatar(&config, &png(&avatar), &options).unwrap();
+        assert_ne!(flipped, avatar);
+        assert_eq!(*flipped.get_pixel(0, 0), *avatar.get_pixel(47, 47));
+        let (restored, _) = prepare_avatar(&config, &png(&flipped), &options).unwrap();
+        assert_eq!(restored, avatar);
+    }
+
+    #[test]
+    fn the_event_ring_is_stacked_over_the_tier_ring() {
+        let mut config = test_config("tier-under-event", &ring_image(64, 8));
+        // a thinner blue ring at half the size, scaled up to the tier ring
+        let mut event = ring_image(32, 2);
+        event.pixels_mut().filter(|px| px[3] > 0).for_each(|px| *px = Rgba([30, 30, 200, 255]));
+        let event_path = std::env::temp_dir().join(format!("chaosring-event-{}.png", std::process::id()));
+        event.save(&event_path).unwrap();
+        let tier = config.tiers[0].clone();
+        let options = RingOptions { event: true, ..RingOptions::default() };
+
+        let err = select_ring(&config, &tier, &options).unwrap_err();
+        assert!(err.downcast_ref::<UserRecoverableError>().is_some());
+
+        config.event_ring = Some(event_path);
+        let ring = select_ring(&config, &tier, &options).unwrap();
+        assert_eq!(ring.dimensions(), (64, 64));
+        assert!(is_close(*ring.get_pixel(1, 32), [30, 30, 200], 16), "{:?}", ring.get_pixel(1, 32));
+        assert_eq!(*ring.get_pixel(6, 32), Rgba([200, 30, 30, 255]));
+        assert_eq!(ring.get_pixel(32, 32)[3], 0);
+        // the opening of the avatar is measured on both rings
+        assert!(get_ring_width(&DynamicImage::ImageRgba8(ring)) >= get_ring_width(&DynamicImage::ImageRgba8(ring_image(64, 8))));
+    }
+
+    #[test]
+    fn the_chroma_key_turns_the_background_of_a_jpeg_ring_transparent() {
+        let magenta = Rgba([255, 0, 255, 255]);
+        let mut keyed = ring_image(64, 8);
+        keyed.pixels_mut().filter(|px| px[3] == 0).for_each(|px| *px = magenta);
+        let path = std::env::temp_dir().join(format!("chaosring-chroma-key-{}.jpg", std::process::id()));
+        std::fs::write(&path, encode(&keyed, OutputFormat::Jpeg, Rgb([0, 0, 0]), None).unwrap()).unwrap();
+
+        let chroma_key = ChromaKey { color: Rgb([255, 0, 255]), tolerance: 40 };
+        let ring = load_ring(&path, Some(&chroma_key)).unwrap();
+        assert_eq!(ring.get_pixel(32, 32)[3], 0);
+        assert_eq!(ring.get_pixel(20, 32)[3], 0);
+        assert_eq!(ring.get_pixel(2, 32)[3], 255);
+        assert!(is_close(*ring.get_pixel(2, 32), [200, 30, 30], 8));
+        assert!(validate_ring(&path, Some(&chroma_key), None).is_ok());
+    }
+
+    #[test]
+    fn a_lower_ring_opacity_lowers_the_alpha_of_the_ring() {
+        let ring = ring_image(64, 8);
+        let avatar = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255]));
+        let band_alpha = |ring_opacity| {
+            let options = RingOptions { ring_opacity, ..RingOptions::default() };
+            overlay_ring(&default_config(), &avatar, &ring, &options).unwrap().get_pixel(3, 32)[3]
+        };
+        assert_eq!(band_alpha(None), 255);
+        assert_eq!(band_alpha(Some(100)), 255);
+        assert_eq!(band_alpha(Some(40)), 102);
+        assert_eq!(band_alpha(Some(0)), 0);
+    }
+
+    #[test]
+    fn keep_ring_size_outputs_at_the_resolution_of_the_ring() {
+        let config = test_config("keep-ring-size", &ring_image(256, 32));
+        let tier = config.tiers[0].clone();
+        let avatar = png(&RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255])));
+
+        let shrunk = apply_tier_ring(&config, &tier, &avatar, &RingOptions::default()).unwrap();
+        assert_eq!(dimensions(&shrunk[0]), (64, 64));
+
+        let options = RingOptions { keep_ring_size: true, ..RingOptions::default() };
+        let kept = image_of(&apply_tier_ring(&config, &tier, &avatar, &options).unwrap()[0]);
+        assert_eq!(kept.dimensions(), (256, 256));
+        // the avatar is upscaled to fill the opening of the native ring
+        assert_eq!(*kept.get_pixel(128, 128), Rgba([10, 120, 240, 255]));
+        assert_eq!(*kept.get_pixel(40, 128), Rgba([10, 120, 240, 255]));
+        assert_eq!(*kept.get_pixel(10, 128), Rgba([200, 30, 30, 255]));
+    }
+
+    #[test]
+    fn progress_of_half_keeps_the_right_half_of_the_ring() {
+        let mut ring = ring_image(64, 8);
+        let opaque = |ring: &RgbaImage| ring.pixels().filter(|px| px[3] != 0).count();
+        let full = opaque(&ring);
+        keep_arc(&mut ring, 50);
+        assert_eq!(opaque(&ring), full / 2);
+        assert_ne!(ring.get_pixel(60, 32)[3], 0);
+        assert_eq!(ring.get_pixel(3, 32)[3], 0);
+    }
+
+    #[test]
+    fn progress_keeps_nothing_to_everything() {
+        let mut ring = ring_image(64, 8);
+        keep_arc(&mut ring, 100);
+        assert_eq!(ring, ring_image(64, 8));
+        keep_arc(&mut ring, 0);
+        assert!(ring.pixels().all(|px| px[3] == 0));
+    }
+
+    #[test]
+    fn the_tint_recolours_a_white_ring() {
+        let mut white_ring = ring_image(64, 8);
+        white_ring.pixels_mut().filter(|px| px[3] > 0).for_each(|px| *px = Rgba([255, 255, 255, 255]));
+        // a transparent avatar leaves the opening of the ring transparent
+        let avatar = RgbaImage::new(64, 64);
+        let options = RingOptions { tint: Some(Rgb([220, 20, 60])), ..RingOptions::default() };
+        let output = overlay_ring(&default_config(), &avatar, &white_ring, &options).unwrap();
+        assert_eq!(*output.get_pixel(3, 32), Rgba([220, 20, 60, 255]));
+        assert_eq!(output.get_pixel(32, 32)[3], 0);
+    }
+
+    #[test]
+    fn crop_trims_the_transparent_margin_around_the_ring() {
+        // ring art drawn on a larger transparent canvas
+        let ring = RgbaImage::from_fn(64, 64, |x, y| {
+            let distance = (x as f32 + 0.5 - 32.0).hypot(y as f32 + 0.5 - 32.0);
+            if (16.0..24.0).contains(&distance) { Rgba([200, 30, 30, 255]) } else { Rgba([0, 0, 0, 0]) }
+        });
+        let config = test_config("crop", &ring);
+        let tier = config.tiers[0].clone();
+        let avatar = png(&RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255])));
+
+        let full = apply_tier_ring(&config, &tier, &avatar, &RingOptions::default()).unwrap();
+        assert_eq!(dimensions(&full[0]), (64, 64));
+        let options = RingOptions { crop: true, ..RingOptions::default() };
+        let cropped = image_of(&apply_tier_ring(&config, &tier, &avatar, &options).unwrap()[0]);
+        assert_eq!(cropped.dimensions(), (48, 48));
+        assert_eq!(*cropped.get_pixel(1, 24), Rgba([200, 30, 30, 255]));
+    }
+
+    #[test]
+    fn sharpening_changes_a_detailed_avatar() {
+        let ring = ring_image(64, 8);
+        let avatar = RgbaImage::from_fn(96, 96, |x, y| if (x / 6 + y / 6) % 2 == 0 { Rgba([60, 60, 60, 255]) } else { Rgba([190, 190, 190, 255]) });
+        let plain = overlay_ring(&default_config(), &avatar, &ring, &RingOptions::default()).unwrap();
+        let options = RingOptions { sharpen: true, ..RingOptions::default() };
+        let sharpened = overlay_ring(&default_config(), &avatar, &ring, &options).unwrap();
+        assert_ne!(plain, sharpened);
+        // the ring itself is not sharpened
+        assert_eq!(plain.get_pixel(3, 32), sharpened.get_pixel(3, 32));
+    }
+
+    #[test]
+    fn the_icon_holds_every_size() {
+        let mut avatar = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255]));
+        apply_mask(&mut avatar, 32, 32.0, 32.0, &Mask::default());
+        let ico = encode_ico(&avatar).unwrap();
+
+        // the directory lists the entries after the 6 bytes of the header, a side of 0 standing for 256
+        assert_eq!(u16::from_le_bytes([ico[4], ico[5]]) as usize, ICO_SIZES.len());
+        let sides = ico[6..].chunks(16).take(ICO_SIZES.len())
+            .map(|entry| if entry[0] == 0 { 256 } else { entry[0] as u32 })
+            .collect::<Vec<u32>>();
+        assert_eq!(sides, ICO_SIZES);
+        // the decoder reads the largest entry, with its transparent corners
+        let decoded = image::load_from_memory_with_format(&ico, ImageFormat::Ico).unwrap().to_rgba8();
+        assert_eq!(decoded.dimensions(), (256, 256));
+        assert_eq!(decoded.get_pixel(0, 0)[3], 0);
+        assert_eq!(decoded.get_pixel(128, 128)[3], 255);
+    }
+
+    #[test]
+    fn each_tier_draws_its_label_in_its_own_colour() {
+        let mut config = test_config("label-colors", &ring_image(128, 16));
+        let mut fren = config.tiers[0].clone();
+        fren.name = String::from("Fren");
+        fren.label_color = Some(Rgb([20, 40, 160]));
+        config.tiers[0].label_color = Some(Rgb([255, 215, 0]));
+        config.tiers.push(fren);
+        let avatar = png(&RgbaImage::from_pixel(128, 128, Rgba([10, 120, 240, 255])));
+        let options = RingOptions { label: true, ..RingOptions::default() };
+
+        let count = |output: &RgbaImage, color: [u8; 3]| output.pixels().filter(|px| px.0 == [color[0], color[1], color[2], 255]).count();
+        let daoist = image_of(&apply_tier_ring(&config, &config.tiers[0], &avatar, &options).unwrap()[0]);
+        let fren = image_of(&apply_tier_ring(&config, &config.tiers[1], &avatar, &options).unwrap()[0]);
+        assert!(count(&daoist, [255, 215, 0]) > 0);
+        assert_eq!(count(&daoist, [20, 40, 160]), 0);
+        assert!(count(&fren, [20, 40, 160]) > 0);
+        assert_eq!(count(&fren, [255, 215, 0]), 0);
+    }
+
+    #[test]
+    fn the_avatar_size_fixes_the_avatar_region() {
+        let ring = square_opening_ring(64, 40);
+        let avatar = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255]));
+        let avatar_region = |config: &Config| {
+            let output = overlay_ring(config, &avatar, &ring, &RingOptions::default()).unwrap();
+            opaque_bounds(&RgbaImage::from_fn(64, 64, |x, y| {
+                if *output.get_pixel(x, y) == Rgba([10, 120, 240, 255]) { Rgba([0, 0, 0, 255]) } else { Rgba([0, 0, 0, 0]) }
+            }))
+        };
+
+        let mut config = default_config();
+        assert_eq!(avatar_region(&config), Some((12, 12, 40)));
+        // centred in the opening
+        config.avatar_size = Some(24);
+        assert_eq!(avatar_region(&config), Some((20, 20, 24)));
+        // and no larger than it
+        config.avatar_size = Some(56);
+        assert_eq!(avatar_region(&config), Some((12, 12, 40)));
+    }
+
+    #[test]
+    fn screen_and_add_lighten_the_colours_under_the_ring() {
+        let grey = Rgba([128, 128, 128, 255]);
+        assert_eq!(blend_premultiplied(&grey, &grey, BlendMode::Normal), grey);
+        assert_eq!(blend_premultiplied(&grey, &grey, BlendMode::Screen), Rgba([192, 192, 192, 255]));
+        assert_eq!(blend_premultiplied(&grey, &grey, BlendMode::Add), Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn a_bright_ring_is_screened_over_a_mid_grey_backdrop() {
+        let mut bright_ring = ring_image(64, 8);
+        bright_ring.pixels_mut().filter(|px| px[3] > 0).for_each(|px| *px = Rgba([200, 200, 200, 255]));
+        let avatar = RgbaImage::from_pixel(64, 64, Rgba([128, 128, 128, 255]));
+        // the band of the ring lies over the background rather than the avatar
+        let mut config = default_config();
+        config.background_image = Some(DynamicImage::ImageRgba8(RgbaImage::from_pixel(64, 64, Rgba([128, 128, 128, 255]))));
+        let band = |config: &Config, blend_mode| {
+            let options = RingOptions { blend_mode, ..RingOptions::default() };
+            *overlay_ring(config, &avatar, &bright_ring, &options).unwrap().get_pixel(3, 32)
+        };
+
+        assert_eq!(band(&config, None), Rgba([200, 200, 200, 255]));
+        assert_eq!(band(&config, Some(BlendMode::Screen)), Rgba([228, 228, 228, 255]));
+        // the configured mode applies to every path that leaves the option unset
+        config.blend_mode = BlendMode::Screen;
+        assert_eq!(band(&config, None), Rgba([228, 228, 228, 255]));
+        assert_eq!(band(&config, Some(BlendMode::Normal)), Rgba([200, 200, 200, 255]));
+    }
+
+    /// An ICC profile of the Display P3 colour space: the white point and curve of sRGB with wider primaries
+    fn display_p3_profile() -> Vec<u8> {
+        let xy = |x, y| lcms2::CIExyY { x, y, Y: 1.0 };
+        let primaries = lcms2::CIExyYTRIPLE { Red: xy(0.680, 0.320), Green: xy(0.265, 0.690), Blue: xy(0.150, 0.060) };
+        let srgb_curve = lcms2::ToneCurve::new_parametric(4, &[2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045]).unwrap();
+        Profile::new_rgb(&xy(0.3127, 0.3290), &primaries, &[&srgb_curve, &srgb_curve, &srgb_curve]).unwrap().icc().unwrap()
+    }
+
+    #[test]
+    fn converts_a_display_p3_avatar_to_srgb() {
+        let avatar = RgbaImage::from_fn(16, 16, |x, _| if x < 8 { Rgba([120, 160, 90, 255]) } else { Rgba([128, 128, 128, 255]) });
+        let mut info = png::Info::with_size(16, 16);
+        info.color_type = png::ColorType::Rgba;
+        info.bit_depth = png::BitDepth::Eight;
+        info.icc_profile = Some(Cow::Owned(display_p3_profile()));
+        let mut tagged = Vec::new();
+        png::Encoder::with_info(&mut tagged, info).unwrap().write_header().unwrap().write_image_data(avatar.as_raw()).unwrap();
+
+        let (converted, _) = prepare_avatar(&default_config(), &tagged, &RingOptions::default()).unwrap();
+        // the wider gamut shows as more saturated colours in sRGB, greys sharing the white point are kept
+        let green = converted.get_pixel(0, 0);
+        assert!(green[0] < 120 && green[1] > 160, "{:?}", green);
+        assert!(is_close(*converted.get_pixel(15, 0), [128, 128, 128], 1));
+        // untagged avatars are taken as sRGB
+        let (untouched, _) = prepare_avatar(&default_config(), &png(&avatar), &RingOptions::default()).unwrap();
+        assert_eq!(untouched, avatar);
+    }
+
+    #[test]
+    fn the_reveal_is_a_gif_with_a_frame_per_step() {
+        use image::AnimationDecoder;
+
+        let mut config = test_config("reveal", &ring_image(64, 8));
+        config.reveal.frames = 4;
+        let tier = config.tiers[0].clone();
+        let avatar = png(&RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255])));
+        let options = RingOptions { animate_reveal: true, ..RingOptions::default() };
+        let attachments = apply_tier_ring(&config, &tier, &avatar, &options).unwrap();
+        assert_eq!(attachments.len(), 2);
+
+        let gif = match &attachments[1] {
+            AttachmentType::Bytes { data, filename } if filename == "avatar-reveal.gif" => data.to_vec(),
+            _ => panic!("expected the reveal GIF"),
+        };
+        let frames = image::codecs::gif::GifDecoder::new(Cursor::new(gif)).unwrap()
+            .into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 4);
+        // the left of the ring is drawn last
+        assert!(!is_close(*frames[0].buffer().get_pixel(3, 32), [200, 30, 30], 8));
+        assert!(is_close(*frames[3].buffer().get_pixel(3, 32), [200, 30, 30], 8));
+    }
+
+    #[test]
+    fn an_indexed_png_keeps_the_transparency_of_its_palette() {
+        // palette entries: opaque blue, fully transparent and half transparent red
+        let mut indexed = Vec::new();
+        let mut encoder = png::Encoder::new(&mut indexed, 4, 1);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(vec![10, 120, 240, 0, 0, 0, 200, 30, 30]);
+        encoder.set_trns(vec![255, 0, 128]);
+        encoder.write_header().unwrap().write_image_data(&[0, 1, 2, 0]).unwrap();
+
+        let (avatar, _) = prepare_avatar(&default_config(), &indexed, &RingOptions::default()).unwrap();
+        assert_eq!(avatar.pixels().copied().collect::<Vec<Rgba<u8>>>(), vec![
+            Rgba([10, 120, 240, 255]),
+            Rgba([0, 0, 0, 0]),
+            Rgba([200, 30, 30, 128]),
+            Rgba([10, 120, 240, 255]),
+        ]);
+    }
+
+    /// Bounding box of the pixels of the colour, as (x, y, side)
+    fn bounds_of(image: &RgbaImage, color: Rgba<u8>) -> Option<(u32, u32, u32)> {
+        opaque_bounds(&RgbaImage::from_fn(image.width(), image.height(), |x, y| {
+            if *image.get_pixel(x, y) == color { Rgba([0, 0, 0, 255]) } else { Rgba([0, 0, 0, 0]) }
+        }))
+    }
+
+    #[test]
+    fn a_ring_smaller_than_the_canvas_is_centred_around_the_avatar() {
+        let mut config = default_config();
+        config.canvas_padding = 8;
+        let avatar = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255]));
+        let output = overlay_ring(&config, &avatar, &square_opening_ring(64, 32), &RingOptions::default()).unwrap();
+        assert_eq!(output.dimensions(), (80, 80));
+        assert_eq!(bounds_of(&output, Rgba([10, 120, 240, 255])), Some((24, 24, 32)));
+        assert_eq!(*output.get_pixel(23, 40), Rgba([200, 30, 30, 255]));
+        assert_eq!(*output.get_pixel(56, 40), Rgba([200, 30, 30, 255]));
+    }
+
+    #[test]
+    fn the_ring_offset_moves_the_avatar_with_the_opening() {
+        let mut config = default_config();
+        config.canvas_padding = 8;
+        config.ring_offset = (4, -2);
+        let avatar = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255]));
+        let output = overlay_ring(&config, &avatar, &square_opening_ring(64, 32), &RingOptions::default()).unwrap();
+        assert_eq!(bounds_of(&output, Rgba([10, 120, 240, 255])), Some((28, 22, 32)));
+        // the ring still frames the avatar on every side
+        for (x, y) in [(27, 38), (60, 38), (44, 21), (44, 54)] {
+            assert_eq!(*output.get_pixel(x, y), Rgba([200, 30, 30, 255]), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn the_canvas_padding_keeps_the_outer_edge_of_the_ring() {
+        let ring = ring_image(64, 8);
+        let avatar = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255]));
+        let mut config = default_config();
+        config.mask.feather = 2.0;
+        let clipped = overlay_ring(&config, &avatar, &ring, &RingOptions::default()).unwrap();
+        assert!(clipped.get_pixel(0, 32)[3] < 255, "{:?}", clipped.get_pixel(0, 32));
+
+        config.canvas_padding = 4;
+        let padded = overlay_ring(&config, &avatar, &ring, &RingOptions::default()).unwrap();
+        assert_eq!(padded.dimensions(), (72, 72));
+        assert_eq!(*padded.get_pixel(4, 36), Rgba([200, 30, 30, 255]));
+        assert_eq!(*padded.get_pixel(67, 36), Rgba([200, 30, 30, 255]));
+    }
+
+    #[test]
+    fn a_missing_font_leaves_the_label_out() {
+        let mut config = test_config("missing-font", &ring_image(128, 16));
+        let avatar = png(&RgbaImage::from_pixel(128, 128, Rgba([10, 120, 240, 255])));
+        let unlabelled = apply_tier_ring(&config, &config.tiers[0], &avatar, &RingOptions::default()).unwrap();
+
+        config.tiers[0].label_font = Some(std::env::temp_dir().join("chaosring-missing-font.bdf"));
+        let options = RingOptions { label: true, join_days: Some(12), ..RingOptions::default() };
+        let labelled = apply_tier_ring(&config, &config.tiers[0], &avatar, &options).unwrap();
+        assert_eq!(image_of(&labelled[0]), image_of(&unlabelled[0]));
+    }
+
+    #[test]
+    fn a_label_too_long_for_the_avatar_is_left_out() {
+        let mut config = test_config("long-label", &ring_image(64, 8));
+        let avatar = png(&RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255])));
+        let unlabelled = apply_tier_ring(&config, &config.tiers[0], &avatar, &RingOptions::default()).unwrap();
+
+        config.tiers[0].name = String::from("Distinguished Chaos Connoisseur");
+        let options = RingOptions { label: true, ..RingOptions::default() };
+        let labelled = apply_tier_ring(&config, &config.tiers[0], &avatar, &options).unwrap();
+        assert_eq!(image_of(&labelled[0]), image_of(&unlabelled[0]));
+    }
+
+    #[test]
+    fn a_sticker_is_a_square_png_within_the_size_limit() {
+        let sticker = encode_sticker(&default_config(), &noise(640)).unwrap();
+        assert!(sticker.len() <= MAX_STICKER_BYTES, "{} bytes", sticker.len());
+        assert_eq!(image::guess_format(&sticker).unwrap(), ImageFormat::Png);
+        assert_eq!(decode(&sticker).unwrap().dimensions(), (STICKER_SIDE, STICKER_SIDE));
+    }
+
+    #[test]
+    fn the_colours_are_reduced_until_the_png_fits() {
+        let config = default_config();
+        // a gradient with noise in the low bits, like the grain of a photo
+        let grain = noise(320);
+        let image = RgbaImage::from_fn(320, 320, |x, y| {
+            let px = grain.get_pixel(x, y);
+            Rgba([(x / 2) as u8 ^ (px[0] & 7), (y / 2) as u8 ^ (px[1] & 7), 128 ^ (px[2] & 7), 255])
+        });
+        let full = encode(&image, OutputFormat::Png, config.jpeg_background, None).unwrap();
+        let reduced = encode_png_within(&config, &image, full.len() * 3 / 4).unwrap();
+        assert!(reduced.len() <= full.len() * 3 / 4);
+        // the shapes are kept, only the shades are fewer
+        let decoded = decode(&reduced).unwrap().to_rgba8();
+        assert_eq!(decoded.dimensions(), (320, 320));
+        assert!(is_close(*decoded.get_pixel(10, 10), image.get_pixel(10, 10).0[..3].try_into().unwrap(), 7));
+
+        let err = encode_png_within(&config, &image, 1024).unwrap_err();
+        assert!(err.downcast_ref::<UserRecoverableError>().is_some());
+    }
+
+    #[test]
+    fn warns_below_the_soft_floor_and_refuses_below_the_hard_one() {
+        let mut config = default_config();
+        config.min_dimension = Some(128);
+        config.min_dimension_hard = Some(64);
+        let avatar = |side| png(&RgbaImage::from_pixel(side, side, Rgba([10, 120, 240, 255])));
+
+        assert_eq!(check_min_dimension(&config, &avatar(128)).unwrap(), None);
+        let warning = check_min_dimension(&config, &avatar(96)).unwrap().unwrap();
+        assert_eq!(warning, "Your image is only 96x96, upload one of at least 128x128 for a sharper result.");
+        let err = check_min_dimension(&config, &avatar(48)).unwrap_err();
+        assert!(err.downcast_ref::<UserRecoverableError>().is_some());
+        assert!(check_min_dimension(&config, &avatar(64)).unwrap().is_some());
+    }
+
+    #[test]
+    fn the_background_image_shows_through_a_transparent_avatar() {
+        let mut config = default_config();
+        // a striped texture, resized to the canvas
+        config.background_image = Some(DynamicImage::ImageRgba8(RgbaImage::from_fn(32, 32, |x, _| {
+            if x < 16 { Rgba([250, 240, 100, 255]) } else { Rgba([40, 160, 60, 255]) }
+        })));
+        // the left half of the avatar is transparent
+        let avatar = RgbaImage::from_fn(64, 64, |x, _| if x < 32 { Rgba([0, 0, 0, 0]) } else { Rgba([10, 120, 240, 255]) });
+        let output = overlay_ring(&config, &avatar, &ring_image(64, 8), &RingOptions::default()).unwrap();
+
+        assert_eq!(*output.get_pixel(20, 32), Rgba([250, 240, 100, 255]));
+        assert_eq!(*output.get_pixel(44, 32), Rgba([10, 120, 240, 255]));
+        assert_eq!(*output.get_pixel(3, 32), Rgba([200, 30, 30, 255]));
+        // the mask still clears the corners
+        assert_eq!(output.get_pixel(0, 0)[3], 0);
+    }
+
+    #[test]
+    fn file_names_keep_the_ascii_part_of_the_user_name() {
+        assert_eq!(user_filename("chaos_fren-42", UserId(7), "avatar.png"), "chaos_fren-42-avatar.png");
+        assert_eq!(user_filename("Zoë the DAOist 🌀", UserId(7), "avatar.png"), "Zo_the_DAOist-avatar.png");
+        // nothing usable is left of emoji and CJK names
+        assert_eq!(user_filename("🌀🔥🌀", UserId(1234567890), "avatar.png"), "1234567890-avatar.png");
+        assert_eq!(user_filename("混沌の輪", UserId(1234567890), "sticker.png"), "1234567890-sticker.png");
+    }
+
+    #[test]
+    fn the_forced_tier_overrides_the_roles() {
+        let mut config = test_config("force-tier", &ring_image(64, 8));
+        let mut fren = config.tiers[0].clone();
+        fren.name = String::from("Fren");
+        fren.role_ids = vec![2];
+        config.tiers.push(fren);
+
+        assert_eq!(resolve_tier(&config, &[RoleId(1)]).unwrap().name, "DAOist");
+        assert!(resolve_tier(&config, &[]).is_err());
+        config.force_tier = Some(String::from("fren"));
+        assert_eq!(resolve_tier(&config, &[RoleId(1)]).unwrap().name, "Fren");
+        assert_eq!(resolve_tier(&config, &[]).unwrap().name, "Fren");
+        config.force_tier = Some(String::from("Whale"));
+        assert!(resolve_tier(&config, &[RoleId(1)]).is_err());
+    }
+
+    #[test]
+    fn grayscale_turns_the_avatar_grey_but_not_the_ring() {
+        let config = test_config("grayscale", &ring_image(64, 8));
+        let avatar = png(&RgbaImage::from_fn(64, 64, |x, y| Rgba([(x * 4) as u8, (y * 4) as u8, 200, 255])));
+        let options = RingOptions { style: AvatarStyle::Grayscale, ..RingOptions::default() };
+        let output = image_of(&apply_tier_ring(&config, &config.tiers[0], &avatar, &options).unwrap()[0]);
+
+        for (x, y) in [(20, 20), (32, 32), (40, 28), (28, 44)] {
+            let px = output.get_pixel(x, y);
+            assert!(px[0] == px[1] && px[1] == px[2], "({}, {}) {:?}", x, y, px);
+        }
+        assert_eq!(*output.get_pixel(3, 32), Rgba([200, 30, 30, 255]));
+    }
+
+    #[test]
+    fn a_ring_without_transparency_is_refused() {
+        let rgb_path = std::env::temp_dir().join(format!("chaosring-rgb-ring-{}.png", std::process::id()));
+        DynamicImage::ImageRgba8(ring_image(64, 8)).to_rgb8().save(&rgb_path).unwrap();
+        let err = validate_ring(&rgb_path, None, None).unwrap_err();
+        assert!(err.to_string().contains("has no alpha channel"), "{}", err);
+        assert!(err.to_string().contains(&rgb_path.display().to_string()));
+
+        let opaque_path = std::env::temp_dir().join(format!("chaosring-opaque-ring-{}.png", std::process::id()));
+        RgbaImage::from_pixel(64, 64, Rgba([200, 30, 30, 255])).save(&opaque_path).unwrap();
+        let err = validate_ring(&opaque_path, None, None).unwrap_err();
+        assert!(err.to_string().contains("has no transparent pixels"), "{}", err);
+
+        let config = test_config("valid-ring", &ring_image(64, 8));
+        assert!(validate_ring(&config.tiers[0].ring, None, None).is_ok());
+    }
+
+    #[test]
+    fn a_ring_with_a_thin_band_is_refused() {
+        let config = test_config("thin-band", &ring_image(64, 2));
+        let err = validate_ring(&config.tiers[0].ring, None, Some(4)).unwrap_err();
+        assert!(err.to_string().contains("was it exported at the wrong scale?"), "{}", err);
+        assert!(validate_ring(&config.tiers[0].ring, None, None).is_ok());
+
+        let config = test_config("thick-band", &ring_image(64, 8));
+        assert!(validate_ring(&config.tiers[0].ring, None, Some(4)).is_ok());
+    }
+
+    #[test]
+    fn a_half_transparent_avatar_stays_transparent_rather_than_black() {
+        let avatar = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 128]));
+        let output = overlay_ring(&default_config(), &avatar, &square_opening_ring(64, 32), &RingOptions::default()).unwrap();
+        assert_eq!(*output.get_pixel(32, 32), Rgba([10, 120, 240, 128]));
+
+        let decoded = image::load_from_memory(&encode(&output, OutputFormat::Png, Rgb([255, 255, 255]), None).unwrap())
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(*decoded.get_pixel(32, 32), Rgba([10, 120, 240, 128]));
+    }
+
+    #[test]
+    fn the_variant_option_picks_the_named_ring() {
+        let mut config = test_config("variant-default", &ring_image(64, 8));
+        let mut holiday = ring_image(64, 8);
+        for px in holiday.pixels_mut().filter(|px| px[3] > 0) {
+            *px = Rgba([30, 200, 30, 255]);
+        }
+        let holiday_path = std::env::temp_dir().join(format!("chaosring-variant-holiday-{}.png", std::process::id()));
+        holiday.save(&holiday_path).unwrap();
+        config.tiers[0].variants.insert(String::from("holiday"), holiday_path);
+        let tier = &config.tiers[0];
+
+        let ring = select_ring(&config, tier, &RingOptions::default()).unwrap();
+        assert!(is_close(*ring.get_pixel(2, 32), [200, 30, 30], 0));
+        let options = RingOptions { variant: Some(String::from("holiday")), ..Default::default() };
+        let ring = select_ring(&config, tier, &options).unwrap();
+        assert!(is_close(*ring.get_pixel(2, 32), [30, 200, 30], 0));
+
+        let options = RingOptions { variant: Some(String::from("spooky")), ..Default::default() };
+        let err = select_ring(&config, tier, &options).unwrap_err();
+        assert!(err.to_string().contains("available: holiday"), "{}", err);
+    }
+
+    #[test]
+    fn linear_output_darkens_the_mid_tones_and_keeps_the_alpha() {
+        let avatar = RgbaImage::from_pixel(16, 16, Rgba([128, 0, 255, 100]));
+        let mut config = default_config();
+        let srgb = finish(&config, avatar.clone(), OutputFormat::Png, &RingOptions::default()).unwrap();
+        assert_eq!(*image_of(&srgb[0]).get_pixel(8, 8), Rgba([128, 0, 255, 100]));
+
+        config.linear_output = true;
+        let linear = finish(&config, avatar, OutputFormat::Png, &RingOptions::default()).unwrap();
+        assert_eq!(*image_of(&linear[0]).get_pixel(8, 8), Rgba([55, 0, 255, 100]));
+    }
+
+    #[test]
+    fn the_join_badge_is_stamped_on_the_ring() {
+        let config = test_config("join-badge", &ring_image(128, 16));
+        let avatar = png(&RgbaImage::from_pixel(128, 128, Rgba([10, 120, 240, 255])));
+        let plain = image_of(&apply_tier_ring(&config, &config.tiers[0], &avatar, &RingOptions::default()).unwrap()[0]);
+        let options = RingOptions { join_days: Some(60), ..RingOptions::default() };
+        let badged = image_of(&apply_tier_ring(&config, &config.tiers[0], &avatar, &options).unwrap()[0]);
+        assert_ne!(badged, plain);
+        // only the band is stamped, the avatar in the opening is left as is
+        assert_eq!(badged.get_pixel(64, 64), plain.get_pixel(64, 64));
+    }
+
+    #[test]
+    fn smart_crop_centres_the_window_on_an_off_centre_subject() {
+        let mut image = RgbaImage::from_pixel(200, 100, Rgba([128, 128, 128, 255]));
+        replace(&mut image, &noise(30), 120, 35);
+        let cropped = smart_crop(image);
+        assert_eq!(cropped.dimensions(), (100, 100));
+        // the subject, centred around x = 135, is in the middle of the window starting around x = 85
+        let subject = (0..100).filter(|x| cropped.get_pixel(*x, 50)[0] != 128).collect::<Vec<u32>>();
+        assert!(subject.first().is_some_and(|x| (30..=40).contains(x)), "{:?}", subject);
+        assert!(subject.last().is_some_and(|x| (60..=70).contains(x)), "{:?}", subject);
+
+        // without any detail the centred crop of the resize is left to do
+        let flat = RgbaImage::from_pixel(200, 100, Rgba([128, 128, 128, 255]));
+        assert_eq!(smart_crop(flat.clone()), flat);
+    }
+
+    #[test]
+    fn the_gps_exif_of_an_uploaded_jpeg_is_not_in_the_output() {
+        let photo = RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255]));
+        let jpeg = encode(&photo, OutputFormat::Jpeg, Rgb([0, 0, 0]), None).unwrap();
+        // big-endian TIFF header, IFD0 with a single entry pointing (0x8825, LONG) to the GPS IFD at offset 26,
+        // which holds the latitude reference (0x0001, ASCII) "N"
+        let tiff = [
+            b"MM\0\x2a\0\0\0\x08".as_slice(),
+            &[0, 1], &[0x88, 0x25, 0, 4, 0, 0, 0, 1, 0, 0, 0, 26], &[0, 0, 0, 0],
+            &[0, 1], &[0x00, 0x01, 0, 2, 0, 0, 0, 2, b'N', 0, 0, 0], &[0, 0, 0, 0],
+        ].concat();
+        let upload = with_exif(&jpeg, &tiff);
+        let exif = exif::Reader::new().read_from_container(&mut Cursor::new(&upload)).unwrap();
+        assert!(exif.get_field(exif::Tag::GPSLatitudeRef, exif::In::PRIMARY).is_some());
+
+        let mut config = test_config("gps-exif", &ring_image(64, 8));
+        for preserve_format in [false, true] {
+            config.preserve_format = preserve_format;
+            let attachments = apply_tier_ring(&config, &config.tiers[0], &upload, &RingOptions::default()).unwrap();
+            let data = match &attachments[0] {
+                AttachmentType::Bytes { data, .. } => data,
+                _ => panic!("expected an attachment in memory"),
+            };
+            assert!(exif::Reader::new().read_from_container(&mut Cursor::new(data.as_ref())).is_err());
+            assert!(!data.windows(4).any(|window| window == b"Exif" || window == b"eXIf"));
+        }
+    }
+
+    #[test]
+    fn demo_mode_picks_any_tier_whatever_the_roles() {
+        let mut config = test_config("random-tier", &ring_image(64, 8));
+        let mut whale = config.tiers[0].clone();
+        whale.name = String::from("Whale");
+        whale.role_ids = vec![2];
+        config.tiers.push(whale);
+        assert!(resolve_tier(&config, &[RoleId(3)]).is_err());
+
+        config.random_tier = true;
+        let picked = (0..200)
+            .map(|_| resolve_tier(&config, &[RoleId(3)]).unwrap().name.clone())
+            .collect::<std::collections::HashSet<String>>();
+        assert_eq!(picked.len(), 2, "{:?}", picked);
+    }
+
+    /// Fails with the error of `failure` until it has been asked `failures` times, then returns `image`
+    struct FlakySource {
+        failures: usize,
+        failure: fn() -> anyhow::Error,
+        image: Vec<u8>,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    #[serenity::async_trait]
+    impl AvatarSource for FlakySource {
+        async fn download(&self) -> anyhow::Result<Vec<u8>> {
+            if self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < self.failures {
+                return Err((self.failure)());
+            }
+            Ok(self.image.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn an_unexpected_download_failure_is_retried_until_it_succeeds() {
+        let config = test_config("download-retry", &ring_image(64, 8));
+        let image = png(&RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255])));
+        let source = FlakySource { failures: 1, failure: || anyhow::anyhow!("connection reset"), image: image.clone(), calls: Default::default() };
+        assert_eq!(download(&config, &source).await.unwrap(), image);
+        assert_eq!(source.calls.into_inner(), 2);
+
+        let source = FlakySource { failures: 1, failure: || anyhow::anyhow!("connection reset"), image: image.clone(), calls: Default::default() };
+        let ringed = run(&config, &[RoleId(1)], &source, &RingOptions::default()).await.unwrap();
+        assert_eq!(dimensions(&ringed.attachments[0]), (64, 64));
+
+        // the user's own mistakes come back at once, and the retries stop after a few attempts
+        let source = FlakySource { failures: 1, failure: || UserRecoverableError::new("Not an image").into(), image: image.clone(), calls: Default::default() };
+        assert!(download(&config, &source).await.is_err());
+        assert_eq!(source.calls.into_inner(), 1);
+        let source = FlakySource { failures: usize::MAX, failure: || anyhow::anyhow!("connection reset"), image, calls: Default::default() };
+        assert!(download(&config, &source).await.is_err());
+        assert_eq!(source.calls.into_inner(), DOWNLOAD_ATTEMPTS as usize);
+    }
+
+    #[test]
+    fn an_emoji_without_an_opening_is_refused_as_a_ring() {
+        assert_eq!(emoji_ring(&png(&ring_image(64, 8))).unwrap().dimensions(), (64, 64));
+
+        let err = emoji_ring(&png(&RgbaImage::from_pixel(64, 64, Rgba([255, 200, 0, 255])))).unwrap_err();
+        assert!(err.downcast_ref::<UserRecoverableError>().is_some());
+        assert!(err.to_string().contains("no transparent centre"));
+    }
+
+    #[test]
+    fn the_crop_option_shrinks_the_padded_canvas_to_the_circle() {
+        let mut config = test_config("crop-canvas", &ring_image(64, 8));
+        config.canvas_padding = 8;
+        let avatar = png(&RgbaImage::from_pixel(64, 64, Rgba([10, 120, 240, 255])));
+        let padded = apply_tier_ring(&config, &config.tiers[0], &avatar, &RingOptions::default()).unwrap();
+        assert_eq!(dimensions(&padded[0]), (80, 80));
+
+        let options = RingOptions { crop: true, ..RingOptions::default() };
+        let cropped = apply_tier_ring(&config, &config.tiers[0], &avatar, &options).unwrap();
+        assert_eq!(dimensions(&cropped[0]), (64, 64));
+    }
+
+    /// Answers after `delay`, like an avatar on a slow server
+    struct SlowSource {
+        delay: Duration,
+        image: Vec<u8>,
+    }
+
+    #[serenity::async_trait]
+    impl AvatarSource for SlowSource {
+        async fn download(&self) -> anyhow::Result<Vec<u8>> {
+            tokio::time::sleep(self.delay).await;
+            Ok(self.image.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn a_slow_download_is_logged_with_its_duration_and_size() {
+        let output = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let writer = {
+            let output = output.clone();
+            move || crate::tests::SharedBuffer(output.clone())
+        };
+        // the test runtime runs on this thread only, so the subscriber sees the events of the download
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::fmt().with_writer(writer).with_ansi(false).finish());
+
+        let mut config = default_config();
+        config.slow_download = Some(Duration::from_millis(20));
+        let fast = SlowSource { delay: Duration::ZERO, image: vec![0; 100] };
+        download(&config, &fast).await.unwrap();
+        assert!(output.lock().unwrap().is_empty());
+
+        let slow = SlowSource { delay: Duration::from_millis(50), image: vec![0; 1234] };
+        download(&config, &slow).await.unwrap();
+        let logs = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("WARN"), "{}", logs);
+        assert!(logs.contains("Downloading the avatar took"), "{}", logs);
+        assert!(logs.contains("for 1234 bytes"), "{}", logs);
+    }
+
+    #[test]
+    fn each_combination_of_mask_flags() {
+        for clear_outer in [false, true] {
+            for feather in [0.0, 4.0] {
+                for vignette in [0.0, 0.5] {
+                    let mask = Mask { clear_outer, feather, vignette };
+                    let mut image = RgbaImage::from_pixel(64, 64, Rgba([255, 255, 255, 255]));
+                    apply_mask(&mut image, 32, 32.0, 32.0, &mask);
+
+                    let corner_alpha = if clear_outer { 0 } else { 255 };
+                    assert_eq!(image.get_pixel(0, 0)[3], corner_alpha, "{:?}", mask);
+                    // 2 pixels inside the edge, half way through a feather of 4 pixels
+                    let edge_alpha = if clear_outer && feather > 0.0 { 128 } else { 255 };
+                    assert_eq!(image.get_pixel(62, 32)[3], edge_alpha, "{:?}", mask);
+                    // a quarter of the vignette at half the radius
+                    let middle = if vignette > 0.0 { 223 } else { 255 };
+                    assert_eq!(*image.get_pixel(48, 32), Rgba([middle, middle, middle, 255]), "{:?}", mask);
+                    assert_eq!(*image.get_pixel(32, 32), Rgba([255, 255, 255, 255]), "{:?}", mask);
+                }
+            }
+        }
+    }
 }
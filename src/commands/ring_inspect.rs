@@ -0,0 +1,43 @@
+use image::DynamicImage;
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::permissions::Permissions;
+use serenity::model::prelude::Attachment;
+use serenity::model::prelude::command::CommandOptionType;
+
+use crate::commands::ring::{apply_chroma_key, decode, find_opening, get_ring_width};
+use crate::config::Config;
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("ring-inspect")
+        .description("Show how the bot sees a ring image")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+        .create_option(
+            |option| {
+                option
+                    .name("ring")
+                    .description("A ring image")
+                    .kind(CommandOptionType::Attachment)
+                    .required(true)
+            },
+        )
+}
+
+/// Describe the dimensions and the detected band and opening of the ring
+pub async fn run(config: &Config, ring: &Attachment) -> anyhow::Result<String> {
+    let ring = ring.download().await?;
+    let mut ring = decode(&ring)?.to_rgba8();
+    if let Some(chroma_key) = &config.chroma_key {
+        apply_chroma_key(&mut ring, chroma_key);
+    }
+
+    let (width, height) = ring.dimensions();
+    let square = if width == height { "square" } else { "not square, it will be padded with transparency" };
+    let band = get_ring_width(&DynamicImage::ImageRgba8(ring.clone()));
+    let opening = match find_opening(&ring) {
+        Some((x, y, width, height)) => format!("{}x{} at ({}, {})", width, height, x, y),
+        None => String::from("not found, the band width is used instead"),
+    };
+
+    Ok(format!("Dimensions: {}x{} ({})\nBand width: {} pixels\nTransparent opening: {}", width, height, square, band, opening))
+}
@@ -0,0 +1,178 @@
+use serenity::builder::CreateApplicationCommand;
+use serenity::http::{Http, HttpError, StatusCode};
+use serenity::model::id::{EmojiId, GuildId};
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::Error as SerenityError;
+
+use crate::commands::ring::UserRecoverableError;
+use crate::download::AvatarSource;
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("ring-prefs")
+        .description("Save the options used by /ring when you leave them out")
+        .create_option(
+            |option| {
+                option
+                    .name("ring_behind")
+                    .description("Draw the avatar over the ring instead of under it")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("dpi")
+                    .description("Print resolution stored in the PNG metadata")
+                    .kind(CommandOptionType::Integer)
+                    .min_int_value(1)
+                    .max_int_value(10000)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("label")
+                    .description("Write the name of your tier on the ring")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("shadow")
+                    .description("Add a drop shadow under the ringed avatar")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("flip_h")
+                    .description("Mirror the avatar horizontally")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("flip_v")
+                    .description("Mirror the avatar vertically")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("ring_opacity")
+                    .description("Opacity of the ring in percent (100 by default)")
+                    .kind(CommandOptionType::Integer)
+                    .min_int_value(0)
+                    .max_int_value(100)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("keep_ring_size")
+                    .description("Output at the ring's resolution even when the avatar is smaller")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("join_badge")
+                    .description("Stamp the number of days since you joined the server on the ring")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("emoji")
+                    .description("Custom emoji of the server (like <:name:id>) to use as your ring")
+                    .kind(CommandOptionType::String)
+                    .required(false)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("reset")
+                    .description("Forget all your saved options")
+                    .kind(CommandOptionType::Boolean)
+                    .required(false)
+            },
+        )
+}
+
+/// The id of a custom emoji written as `<:name:id>`, `<a:name:id>` for animated ones, or as the bare id
+pub fn parse_custom_emoji(value: &str) -> Result<u64, UserRecoverableError> {
+    let value = value.trim();
+    let id = match value.strip_prefix('<').and_then(|value| value.strip_suffix('>')) {
+        Some(mention) => mention.rsplit(':').next().unwrap_or_default(),
+        None => value,
+    };
+    id.parse::<u64>()
+        .map_err(|_| UserRecoverableError::new(format!("{} is not a custom emoji, expected one like <:name:id>", value)))
+}
+
+/// Refuse the emojis that are not custom emojis of the server the command is used in
+pub async fn check_guild_emoji(http: &Http, guild_id: Option<GuildId>, id: u64) -> anyhow::Result<()> {
+    let guild_id = guild_id
+        .ok_or_else(|| UserRecoverableError::new("Custom emoji rings can only be used in a server"))?;
+    match guild_id.emoji(http, EmojiId(id)).await {
+        Ok(_) => Ok(()),
+        Err(SerenityError::Http(err)) if matches!(err.as_ref(), HttpError::UnsuccessfulRequest(response) if response.status_code == StatusCode::NOT_FOUND) => {
+            Err(UserRecoverableError::new("The emoji is not a custom emoji of this server, it may have been deleted").into())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Link to the image of a custom emoji, the first frame of animated ones
+pub fn emoji_url(id: u64) -> String {
+    format!("https://cdn.discordapp.com/emojis/{}.png?size=512", id)
+}
+
+/// Fetch the image of a saved ring emoji, any failure meaning it is gone for the user
+pub async fn download_emoji(emoji: &dyn AvatarSource) -> anyhow::Result<Vec<u8>> {
+    emoji.download().await
+        .map_err(|_| UserRecoverableError::new("Cannot download your ring emoji, it may have been deleted").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::async_trait;
+
+    use super::*;
+
+    /// Stands for the Discord CDN, answering with the image or failing like for a deleted emoji
+    struct FakeCdn(Option<Vec<u8>>);
+
+    #[async_trait]
+    impl AvatarSource for FakeCdn {
+        async fn download(&self) -> anyhow::Result<Vec<u8>> {
+            self.0.clone().ok_or_else(|| anyhow::anyhow!("404 Not Found"))
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_the_emoji_or_tells_it_may_be_deleted() {
+        assert_eq!(download_emoji(&FakeCdn(Some(vec![1, 2, 3]))).await.unwrap(), vec![1, 2, 3]);
+
+        let err = download_emoji(&FakeCdn(None)).await.unwrap_err();
+        let user_recoverable_error = err.downcast_ref::<UserRecoverableError>().unwrap();
+        assert!(user_recoverable_error.to_string().contains("may have been deleted"));
+    }
+}
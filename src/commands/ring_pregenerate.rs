@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use serenity::builder::CreateApplicationCommand;
+use serenity::http::Http;
+use serenity::model::id::{GuildId, UserId};
+use serenity::model::permissions::Permissions;
+use serenity::model::prelude::AttachmentType;
+use tokio::task::{JoinError, JoinSet};
+use tracing::error;
+
+use crate::cache::AvatarCache;
+use crate::commands::ring::{apply_tier_ring, resolve_tier, RingOptions, UserRecoverableError};
+use crate::config::Config;
+use crate::download::{AvatarSource, ImageUrl};
+
+/// Members fetched per request, the most Discord allows
+const MEMBERS_PER_PAGE: u64 = 1000;
+/// Avatars downloaded and rung at the same time
+const CONCURRENCY: usize = 8;
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("ring-pregenerate")
+        .description("Ring the avatar of every member with a ring role ahead of time")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Ring the current avatar of every member holding a tier role, unless already cached, and count the outcomes.
+/// Listing the members requires the bot user to be in the server, the ringing runs on the blocking threads.
+pub async fn run(config: Arc<Config>, http: &Http, guild_id: GuildId, cache: Arc<AvatarCache>) -> anyhow::Result<String> {
+    if config.random_tier {
+        return Err(UserRecoverableError::new("Avatars are not cached while the tiers are picked at random").into());
+    }
+
+    let mut tally = Tally::default();
+    let mut pending = JoinSet::new();
+    let mut after: Option<UserId> = None;
+    loop {
+        let members = guild_id.members(http, Some(MEMBERS_PER_PAGE), after).await?;
+        for member in &members {
+            // the tier the user context-menu command would pick, so that it finds the cached avatar
+            let (tier, avatar_hash) = match (resolve_tier(&config, &member.roles), &member.user.avatar) {
+                (Ok(tier), Some(avatar_hash)) if !member.user.bot => (tier, avatar_hash),
+                _ => continue,
+            };
+            if cache.get(member.user.id, avatar_hash, &tier.name).is_some() {
+                tally.cached += 1;
+                continue;
+            }
+
+            if pending.len() >= CONCURRENCY {
+                if let Some(outcome) = pending.join_next().await {
+                    tally.record(outcome);
+                }
+            }
+            let (config, cache) = (config.clone(), cache.clone());
+            let (user, avatar_hash, tier, avatar_url) = (member.user.id, avatar_hash.clone(), tier.name.clone(), member.user.face());
+            pending.spawn(async move {
+                let result = pregenerate(config, cache, user, avatar_hash, tier, &avatar_url).await;
+                (user, result)
+            });
+        }
+        if (members.len() as u64) < MEMBERS_PER_PAGE {
+            break;
+        }
+        after = members.last().map(|member| member.user.id);
+    }
+    while let Some(outcome) = pending.join_next().await {
+        tally.record(outcome);
+    }
+
+    Ok(format!("Generated {} avatars, {} were already cached and {} failed.", tally.generated, tally.cached, tally.failed))
+}
+
+#[derive(Default)]
+struct Tally {
+    generated: u32,
+    cached: u32,
+    failed: u32,
+}
+
+impl Tally {
+    fn record(&mut self, outcome: Result<(UserId, anyhow::Result<()>), JoinError>) {
+        match outcome {
+            Ok((_, Ok(()))) => self.generated += 1,
+            Ok((user, Err(err))) => {
+                error!("Failed to pregenerate the avatar of user {}: {:#}", user, err);
+                self.failed += 1;
+            }
+            Err(err) => {
+                error!("A pregeneration task failed: {}", err);
+                self.failed += 1;
+            }
+        }
+    }
+}
+
+async fn pregenerate(config: Arc<Config>, cache: Arc<AvatarCache>, user: UserId, avatar_hash: String, tier: String, avatar_url: &str) -> anyhow::Result<()> {
+    let avatar = ImageUrl(avatar_url).download().await?;
+    tokio::task::spawn_blocking(move || {
+        let tier = config.tiers.iter().find(|candidate| candidate.name == tier).context("The tier is gone")?;
+        let attachments = apply_tier_ring(&config, tier, &avatar, &RingOptions::default())?;
+        if let Some(AttachmentType::Bytes { data, filename }) = attachments.first() {
+            let extension = filename.rsplit('.').next().unwrap_or("png");
+            cache.put(user, &avatar_hash, &tier.name, data, extension)?;
+        }
+        Ok(())
+    }).await?
+}
@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use serenity::builder::CreateApplicationCommand;
+
+use crate::commands::ring::load_ring;
+use crate::config::Config;
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("ring-status")
+        .description("Check that the bot is online and its rings load")
+}
+
+/// Names of the tiers (and the event) whose ring cannot be loaded, with the reason
+pub fn check_rings(config: &Config) -> Vec<String> {
+    let rings = config.tiers.iter()
+        .map(|tier| (tier.name.as_str(), &tier.ring))
+        .chain(config.event_ring.iter().map(|ring| ("event", ring)));
+    rings
+        .filter_map(|(name, ring)| load_ring(ring, config.chroma_key.as_ref()).err()
+            .map(|err| format!("{}: {:#}", name, err)))
+        .collect()
+}
+
+/// Describe the uptime, the gateway reconnections, the tiers and whether their rings loaded when the bot connected
+pub fn run(config: &Config, uptime: Duration, reconnections: u64, ring_failures: &[String]) -> String {
+    let secs = uptime.as_secs();
+    let uptime = format!("{}d {}h {}m {}s", secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    let rings = if ring_failures.is_empty() {
+        String::from("all loaded")
+    } else {
+        format!("failed to load\n- {}", ring_failures.join("\n- "))
+    };
+
+    format!("Status: online\nUptime: {}\nReconnections: {}\nTiers: {}\nRings: {}", uptime, reconnections, config.tiers.len(), rings)
+}
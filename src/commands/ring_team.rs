@@ -0,0 +1,129 @@
+use image::{DynamicImage, Rgba, RgbaImage};
+use image::imageops::FilterType;
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::prelude::command::CommandOptionType;
+use serenity::model::prelude::RoleId;
+
+use crate::commands::ring::{apply_tier_ring_to_image, check_min_dimension, prepare_avatar, resolve_tier, RingedAvatar, RingOptions};
+use crate::config::Config;
+use crate::download::AvatarSource;
+
+/// How the ring opening is shared between the two avatars
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Split {
+    /// Side by side, the first avatar on the left
+    #[default]
+    Vertical,
+    /// Along the diagonal from the bottom left to the top right, the first avatar on the top left
+    Diagonal,
+}
+
+impl Split {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "vertical" => Some(Split::Vertical),
+            "diagonal" => Some(Split::Diagonal),
+            _ => None,
+        }
+    }
+}
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("ring-team")
+        .description("Ring two avatars sharing a single ring, for couples and teams")
+        .create_option(
+            |option| {
+                option
+                    .name("avatar")
+                    .description("The first avatar, on the left")
+                    .kind(CommandOptionType::Attachment)
+                    .required(true)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("partner")
+                    .description("The second avatar, on the right")
+                    .kind(CommandOptionType::Attachment)
+                    .required(true)
+            },
+        )
+        .create_option(
+            |option| {
+                option
+                    .name("split")
+                    .description("How the avatars share the ring (vertical by default)")
+                    .kind(CommandOptionType::String)
+                    .add_string_choice("vertical", "vertical")
+                    .add_string_choice("diagonal", "diagonal")
+                    .required(false)
+            },
+        )
+}
+
+/// Ring the two avatars side by side with the tier of the given roles
+pub async fn run<'a>(config: &Config, roles: &[RoleId], first: &dyn AvatarSource, second: &dyn AvatarSource, split: Split, options: &RingOptions) -> anyhow::Result<RingedAvatar<'a>> {
+    let (first, second) = (first.download().await?, second.download().await?);
+    let warning = check_min_dimension(config, &first)?.or(check_min_dimension(config, &second)?);
+    let tier = resolve_tier(config, roles)?;
+
+    let (first, input_format) = prepare_avatar(config, &first, options)?;
+    let (second, _) = prepare_avatar(config, &second, options)?;
+    let team = split_avatars(&first, &second, split);
+    let attachments = apply_tier_ring_to_image(config, tier, team, input_format, options)?;
+
+    Ok(RingedAvatar { tier: Some(tier.name.clone()), attachments, warning })
+}
+
+/// Both avatars cropped to a square of the smaller side and joined along the split line,
+/// the pixels it crosses being blended so that it is anti-aliased
+fn split_avatars(first: &RgbaImage, second: &RgbaImage, split: Split) -> RgbaImage {
+    let side = first.width().min(first.height()).min(second.width()).min(second.height());
+    let first = DynamicImage::ImageRgba8(first.clone()).resize_to_fill(side, side, FilterType::Lanczos3).to_rgba8();
+    let second = DynamicImage::ImageRgba8(second.clone()).resize_to_fill(side, side, FilterType::Lanczos3).to_rgba8();
+
+    RgbaImage::from_fn(side, side, |x, y| {
+        let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+        // signed distance in pixels from the centre of the pixel to the split line, positive on the side of the second avatar
+        let distance = match split {
+            Split::Vertical => cx - side as f32 / 2.0,
+            Split::Diagonal => (cx + cy - side as f32) / std::f32::consts::SQRT_2,
+        };
+        let weight = (distance + 0.5).clamp(0.0, 1.0);
+        let (a, b) = (first.get_pixel(x, y), second.get_pixel(x, y));
+        let mut px = [0u8; 4];
+        for channel in 0..4 {
+            px[channel] = (a[channel] as f32 * (1.0 - weight) + b[channel] as f32 * weight).round() as u8;
+        }
+        Rgba(px)
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+    #[test]
+    fn splits_vertically() {
+        let team = split_avatars(&RgbaImage::from_pixel(64, 64, RED), &RgbaImage::from_pixel(96, 96, BLUE), Split::Vertical);
+        assert_eq!(team.dimensions(), (64, 64));
+        assert_eq!(*team.get_pixel(10, 32), RED);
+        assert_eq!(*team.get_pixel(53, 32), BLUE);
+    }
+
+    #[test]
+    fn splits_along_the_diagonal() {
+        let team = split_avatars(&RgbaImage::from_pixel(64, 64, RED), &RgbaImage::from_pixel(64, 64, BLUE), Split::Diagonal);
+        assert_eq!(*team.get_pixel(5, 5), RED);
+        assert_eq!(*team.get_pixel(58, 58), BLUE);
+        // the top right and bottom left corners are on the split line
+        let corner = team.get_pixel(63, 0);
+        assert!(corner[0] > 0 && corner[2] > 0);
+    }
+}
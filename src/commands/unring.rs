@@ -0,0 +1,79 @@
+use std::borrow::Cow;
+
+use image::{DynamicImage, RgbaImage};
+use image::imageops::{crop_imm, FilterType, resize};
+use serenity::builder::CreateApplicationCommand;
+use serenity::model::prelude::AttachmentType;
+use serenity::model::prelude::command::CommandOptionType;
+use tracing::warn;
+
+use crate::commands::ring::{apply_mask, decode, encode_capped, get_ring_width, load_ring, opaque_bounds, UserRecoverableError};
+use crate::config::{Config, Mask, OutputFormat};
+use crate::download::AvatarSource;
+
+pub fn register(command: &mut CreateApplicationCommand) -> &mut CreateApplicationCommand {
+    command
+        .name("unring")
+        .description("Remove a ChaosDAO ring from a previously ringed avatar")
+        .create_option(
+            |option| {
+                option
+                    .name("avatar")
+                    .description("An avatar ringed by this bot")
+                    .kind(CommandOptionType::Attachment)
+                    .required(true)
+            },
+        )
+}
+
+pub async fn run<'a>(config: &Config, ringed_image: &dyn AvatarSource) -> anyhow::Result<AttachmentType<'a>> {
+    let ringed_image = ringed_image.download().await?;
+    let ringed_image = decode(&ringed_image)?.to_rgba8();
+    let avatar = remove_ring(config, &ringed_image)?;
+
+    let attachment = AttachmentType::Bytes {
+        data: Cow::from(encode_capped(config, &avatar, OutputFormat::Png, None)?),
+        filename: String::from("avatar.png"),
+    };
+
+    Ok(attachment)
+}
+
+/// Crop a ringed avatar to the circle inside the ring.
+/// The band width comes from whichever configured ring looks the most like the one on the image.
+fn remove_ring(config: &Config, ringed_image: &RgbaImage) -> anyhow::Result<RgbaImage> {
+    let (x, y, side) = opaque_bounds(ringed_image)
+        .ok_or_else(|| UserRecoverableError::new("The image is fully transparent"))?;
+    let ringed_image = crop_imm(ringed_image, x, y, side, side).to_image();
+
+    let band = config.tiers.iter()
+        .filter_map(|tier| load_ring(&tier.ring, config.chroma_key.as_ref())
+            .map_err(|err| warn!("Cannot load the {} ring: {}", tier.name, err))
+            .ok())
+        .map(|ring| resize(&ring, side, side, FilterType::Nearest))
+        .min_by_key(|ring| ring_difference(&ringed_image, ring))
+        .map(|ring| get_ring_width(&DynamicImage::ImageRgba8(ring)))
+        .ok_or_else(|| anyhow::anyhow!("No ring could be loaded"))?;
+
+    if 2 * band >= side {
+        return Err(UserRecoverableError::new("No ring found on the image").into());
+    }
+
+    let inner_side = side - 2 * band;
+    let mut avatar = crop_imm(&ringed_image, band, band, inner_side, inner_side).to_image();
+    let center = (inner_side / 2) as f32;
+    apply_mask(&mut avatar, inner_side / 2, center, center, &Mask::default());
+
+    Ok(avatar)
+}
+
+/// Sum of the colour differences over the pixels of the ring's band
+fn ring_difference(ringed_image: &RgbaImage, ring: &RgbaImage) -> u64 {
+    ring.enumerate_pixels()
+        .filter(|(_, _, px)| px[3] != 0)
+        .map(|(x, y, ring_px)| {
+            let px = ringed_image.get_pixel(x, y);
+            (0..3).map(|channel| (px[channel] as i64 - ring_px[channel] as i64).unsigned_abs()).sum::<u64>()
+        })
+        .sum()
+}
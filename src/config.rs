@@ -0,0 +1,890 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context;
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgb};
+use serde::{Deserialize, Deserializer};
+use serenity::model::prelude::{GuildId, RoleId};
+use tracing::warn;
+
+use crate::commands::ring::{UserRecoverableError, validate_ring};
+use crate::label::Font;
+
+/// Ring tiers in order of priority: a member gets the first tier they hold a role for
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    pub tiers: Vec<Tier>,
+    /// Output the colours in linear light rather than sRGB, for compositing in other graphics pipelines
+    #[serde(skip)]
+    pub linear_output: bool,
+    /// Colour filling the transparent areas of JPEG outputs
+    #[serde(skip, default = "default_jpeg_background")]
+    pub jpeg_background: Rgb<u8>,
+    /// Output in the format of the uploaded avatar when the tier doesn't set one
+    #[serde(skip)]
+    pub preserve_format: bool,
+    /// Filter used when shrinking a ring larger than the avatar
+    #[serde(skip, default = "default_ring_filter")]
+    pub ring_filter: FilterType,
+    /// Narrowest band of the rings accepted on startup, thinner ones were likely exported at the wrong scale
+    #[serde(skip)]
+    pub min_band: Option<u32>,
+    /// Downloads of avatars taking longer are logged, to tell slow sources and large files apart from other lag
+    #[serde(skip)]
+    pub slow_download: Option<Duration>,
+    /// Shorter side of the uploaded avatars under which the user is advised to upload a larger one
+    #[serde(skip)]
+    pub min_dimension: Option<u32>,
+    /// Shorter side of the uploaded avatars under which they are refused
+    #[serde(skip)]
+    pub min_dimension_hard: Option<u32>,
+    /// Largest side accepted in the `sizes` option of `/ring`
+    #[serde(skip, default = "default_max_dimension")]
+    pub max_dimension: u32,
+    /// Target size of the encoded outputs
+    #[serde(skip)]
+    pub max_output_bytes: Option<usize>,
+    /// How the ring is composited over the avatar when the `blend` option of `/ring` is left out
+    #[serde(skip)]
+    pub blend_mode: BlendMode,
+    /// Where the `label` option of `/ring` draws the tier name
+    #[serde(skip, default = "default_label_position")]
+    pub label_position: LabelPosition,
+    /// Side of the avatar inside the ring instead of the size of the ring opening, which still bounds it
+    #[serde(skip)]
+    pub avatar_size: Option<u32>,
+    /// Transparent margin added around the ring so that its edge isn't clipped by the circular mask
+    #[serde(skip)]
+    pub canvas_padding: u32,
+    /// Shift of the ring from the center of the canvas in pixels of the ring image, for off-center ring art
+    #[serde(skip)]
+    pub ring_offset: (i32, i32),
+    /// Largest relative difference between the sides of an avatar padded to square instead of cropped
+    #[serde(skip, default = "default_square_tolerance")]
+    pub square_tolerance: f32,
+    /// Crop non-square avatars around their details rather than at their centre
+    #[serde(skip)]
+    pub smart_crop: bool,
+    /// Look of the `shadow` option of `/ring`
+    #[serde(skip)]
+    pub shadow: Shadow,
+    /// Background colour of rings without an alpha channel
+    #[serde(skip)]
+    pub chroma_key: Option<ChromaKey>,
+    /// Strength of the `sharpen` option of `/ring`
+    #[serde(skip)]
+    pub sharpen: Sharpen,
+    /// Animation of the `animate_reveal` option of `/ring`
+    #[serde(skip)]
+    pub reveal: Reveal,
+    /// Masking of the circle of the avatar
+    #[serde(skip)]
+    pub mask: Mask,
+    /// Image showing through the transparent parts of the avatar inside the ring, decoded once on startup
+    #[serde(skip)]
+    pub background_image: Option<DynamicImage>,
+    /// Limited-edition ring stacked over the tier ring by the `event` option of `/ring`
+    #[serde(skip)]
+    pub event_ring: Option<PathBuf>,
+    /// Text of the acknowledgement sent while the avatar is being prepared
+    #[serde(skip, default = "default_ack_message")]
+    pub ack_message: String,
+    /// MIME types accepted for attachments, any image when unset
+    #[serde(skip)]
+    pub allowed_types: Option<Vec<String>>,
+    /// Send errors as embeds rather than plain text
+    #[serde(skip)]
+    pub error_embeds: bool,
+    /// Link to a help page in the error embeds
+    #[serde(skip)]
+    pub help_url: Option<String>,
+    /// How to get a ring role, like a link or a channel mention, shown to members holding none
+    #[serde(skip)]
+    pub role_help: Option<String>,
+    /// Let members ring the avatar of other members from the user context menu
+    #[serde(skip)]
+    pub ring_others: bool,
+    /// Ring a sample avatar with every tier once connected
+    #[serde(skip)]
+    pub self_test: bool,
+    /// Send a low-resolution preview while the full avatar is prepared
+    #[serde(skip)]
+    pub preview: bool,
+    /// Times the registered commands are listed after the registration to confirm they are there, none when 0
+    #[serde(skip)]
+    pub registration_checks: u32,
+    /// Tier applied whatever the roles, for development only
+    #[serde(skip)]
+    pub force_tier: Option<String>,
+    /// Ring everyone with a random tier whatever the roles, to showcase the rings in demos
+    #[serde(skip)]
+    pub random_tier: bool,
+    /// Servers where the commands can be used, any server when unset
+    #[serde(skip)]
+    pub allowed_guilds: Option<Vec<GuildId>>,
+    /// Server where the commands are registered instead of globally, for development
+    #[serde(skip)]
+    pub dev_guild: Option<GuildId>,
+    /// Role whose members are not subject to the `/ring` cooldown
+    #[serde(skip)]
+    pub cooldown_bypass_role: Option<RoleId>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Tier {
+    pub name: String,
+    /// Numbers, or strings checked like the role variables, so that role mentions can be pasted
+    #[serde(deserialize_with = "deserialize_role_ids")]
+    pub role_ids: Vec<u64>,
+    pub ring: PathBuf,
+    /// Factor applied to the final avatar dimensions
+    pub scale: Option<f32>,
+    pub format: Option<OutputFormat>,
+    /// Colour of the tier name drawn by the `label` option, black or white depending on the ring when unset
+    #[serde(default, deserialize_with = "deserialize_color")]
+    pub label_color: Option<Rgb<u8>>,
+    /// BDF bitmap font of the `label` option, the bundled capitals when unset
+    #[serde(default)]
+    pub label_font: Option<PathBuf>,
+    /// Alternative rings (like seasonal ones) by name, picked with the `variant` option of `/ring`
+    #[serde(default)]
+    pub variants: HashMap<String, PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    WebP,
+}
+
+/// Colour made transparent when loading the rings
+#[derive(Debug, Clone, Copy)]
+pub struct ChromaKey {
+    pub color: Rgb<u8>,
+    /// Largest difference on each channel still matching the colour, JPEG compression shifts it slightly
+    pub tolerance: u8,
+}
+
+/// Unsharp mask applied to the avatar
+#[derive(Debug, Clone, Copy)]
+pub struct Sharpen {
+    /// Standard deviation of the blur the avatar is compared to
+    pub sigma: f32,
+    /// Smallest difference with the blurred avatar that gets sharpened
+    pub threshold: i32,
+}
+
+impl Default for Sharpen {
+    fn default() -> Self {
+        Sharpen { sigma: 1.0, threshold: 2 }
+    }
+}
+
+/// Masking of the circle of the avatar
+#[derive(Debug, Clone, Copy)]
+pub struct Mask {
+    /// Clear the pixels outside the circle
+    pub clear_outer: bool,
+    /// Width in pixels of the fade to transparency at the edge of the circle, a hard edge when 0
+    pub feather: f32,
+    /// Darkening of the avatar towards the edge of the circle, between 0 (none) and 1
+    pub vignette: f32,
+}
+
+impl Default for Mask {
+    fn default() -> Self {
+        Mask { clear_outer: true, feather: 0.0, vignette: 0.0 }
+    }
+}
+
+/// Animation drawing the ring in clockwise from the top
+#[derive(Debug, Clone, Copy)]
+pub struct Reveal {
+    /// Frames until the ring is complete
+    pub frames: u32,
+    /// Duration of each frame in milliseconds
+    pub frame_ms: u32,
+}
+
+impl Default for Reveal {
+    fn default() -> Self {
+        Reveal { frames: 12, frame_ms: 80 }
+    }
+}
+
+/// Drop shadow cast towards the bottom right
+#[derive(Debug, Clone, Copy)]
+pub struct Shadow {
+    /// Distance in pixels between the avatar and its shadow
+    pub offset: u32,
+    /// Standard deviation of the gaussian blur
+    pub blur: f32,
+    /// Opacity of the darkest part of the shadow, between 0 and 1
+    pub opacity: f32,
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Shadow { offset: 6, blur: 4.0, opacity: 0.5 }
+    }
+}
+
+/// Blending of the ring colours with the avatar under it, glowing rings looking better in screen or add
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Plain alpha compositing
+    #[default]
+    Normal,
+    /// Lightens by inverting, multiplying and inverting again the colours
+    Screen,
+    /// Sums the colours, capped at white
+    Add,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelPosition {
+    Top,
+    Bottom,
+}
+
+const DEFAULT_CHROMA_KEY_TOLERANCE: u8 = 48;
+
+/// The tiers that can be configured with environment variables alone: (name, role variable, ring variable)
+const ENV_TIERS: [(&str, &str, &str); 3] = [
+    ("DAOist", "DAO_ROLE_DAOIST", "CHAOSRING_DAOISTS"),
+    ("Regular", "DAO_ROLE_REGULAR", "CHAOSRING_REGULARS"),
+    ("Fren", "DAO_ROLE_FREN", "CHAOSRING_FRENS"),
+];
+
+impl Tier {
+    pub fn role_ids(&self) -> impl Iterator<Item=RoleId> + '_ {
+        self.role_ids.iter().map(|role_id| RoleId(*role_id))
+    }
+}
+
+impl Config {
+    /// Load the tiers from the file in `CHAOSRING_CONFIG` if set, otherwise from the environment.
+    /// Environment variables of the built-in tiers override the matching tiers of the file.
+    pub fn load() -> anyhow::Result<Config> {
+        let mut config = match env::var("CHAOSRING_CONFIG") {
+            Ok(path) => Config::from_file(Path::new(&path))?,
+            Err(_) => Config::from_env()?,
+        };
+        config.apply_env_overrides()?;
+        if let Ok(order) = env::var("CHAOSRING_TIER_ORDER") {
+            config.reorder_tiers(&order)
+                .with_context(|| format!("Invalid CHAOSRING_TIER_ORDER value: {}", order))?;
+        }
+        for warning in implausible_role_ids(&config.tiers) {
+            warn!("{}", warning);
+        }
+        if let Ok(color) = env::var("CHAOSRING_JPEG_BG") {
+            config.jpeg_background = parse_hex_color(&color)
+                .with_context(|| format!("Invalid CHAOSRING_JPEG_BG value: {}", color))?;
+        }
+        config.preserve_format = load_flag("CHAOSRING_PRESERVE_FORMAT")?;
+        config.linear_output = load_flag("CHAOSRING_LINEAR_OUTPUT")?;
+        if let Ok(max_output_bytes) = env::var("CHAOSRING_MAX_OUTPUT_BYTES") {
+            config.max_output_bytes = Some(max_output_bytes.parse::<usize>()
+                .with_context(|| format!("Invalid CHAOSRING_MAX_OUTPUT_BYTES value: {}", max_output_bytes))?);
+        }
+        if let Ok(position) = env::var("CHAOSRING_LABEL_POSITION") {
+            config.label_position = match position.trim().to_ascii_lowercase().as_str() {
+                "top" => LabelPosition::Top,
+                "bottom" => LabelPosition::Bottom,
+                _ => anyhow::bail!("Invalid CHAOSRING_LABEL_POSITION value: {}, expected top or bottom", position),
+            };
+        }
+        if let Ok(mode) = env::var("CHAOSRING_BLEND_MODE") {
+            config.blend_mode = parse_blend_mode(&mode)
+                .with_context(|| format!("Invalid CHAOSRING_BLEND_MODE value: {}", mode))?;
+        }
+        if let Ok(offset) = env::var("CHAOSRING_SHADOW_OFFSET") {
+            config.shadow.offset = offset.parse::<u32>()
+                .with_context(|| format!("Invalid CHAOSRING_SHADOW_OFFSET value: {}", offset))?;
+        }
+        if let Ok(blur) = env::var("CHAOSRING_SHADOW_BLUR") {
+            config.shadow.blur = blur.parse::<f32>().ok()
+                .filter(|blur| *blur >= 0.0)
+                .with_context(|| format!("Invalid CHAOSRING_SHADOW_BLUR value: {}", blur))?;
+        }
+        if let Ok(opacity) = env::var("CHAOSRING_SHADOW_OPACITY") {
+            config.shadow.opacity = opacity.parse::<f32>().ok()
+                .filter(|opacity| (0.0..=1.0).contains(opacity))
+                .with_context(|| format!("Invalid CHAOSRING_SHADOW_OPACITY value: {}", opacity))?;
+        }
+        if let Ok(size) = env::var("CHAOSRING_AVATAR_SIZE") {
+            config.avatar_size = Some(size.parse::<u32>().ok()
+                .filter(|size| *size > 0)
+                .with_context(|| format!("Invalid CHAOSRING_AVATAR_SIZE value: {}", size))?);
+        }
+        if let Ok(padding) = env::var("CHAOSRING_CANVAS_PADDING") {
+            config.canvas_padding = padding.parse::<u32>()
+                .with_context(|| format!("Invalid CHAOSRING_CANVAS_PADDING value: {}", padding))?;
+        }
+        if let Ok(offset) = env::var("CHAOSRING_RING_OFFSET") {
+            config.ring_offset = offset.split_once(',')
+                .and_then(|(x, y)| Some((x.trim().parse::<i32>().ok()?, y.trim().parse::<i32>().ok()?)))
+                .with_context(|| format!("Invalid CHAOSRING_RING_OFFSET value: {}, expected x,y", offset))?;
+        }
+        if let Ok(tolerance) = env::var("CHAOSRING_SQUARE_TOLERANCE") {
+            config.square_tolerance = tolerance.parse::<f32>().ok()
+                .filter(|tolerance| *tolerance >= 0.0)
+                .with_context(|| format!("Invalid CHAOSRING_SQUARE_TOLERANCE value: {}", tolerance))?;
+        }
+        config.smart_crop = load_flag("CHAOSRING_SMART_CROP")?;
+        if let Ok(color) = env::var("CHAOSRING_CHROMAKEY") {
+            let color = parse_hex_color(&color)
+                .with_context(|| format!("Invalid CHAOSRING_CHROMAKEY value: {}", color))?;
+            let tolerance = match env::var("CHAOSRING_CHROMAKEY_TOLERANCE") {
+                Ok(tolerance) => tolerance.parse::<u8>()
+                    .with_context(|| format!("Invalid CHAOSRING_CHROMAKEY_TOLERANCE value: {}", tolerance))?,
+                Err(_) => DEFAULT_CHROMA_KEY_TOLERANCE,
+            };
+            config.chroma_key = Some(ChromaKey { color, tolerance });
+        }
+        if let Ok(sigma) = env::var("CHAOSRING_SHARPEN_SIGMA") {
+            config.sharpen.sigma = sigma.parse::<f32>().ok()
+                .filter(|sigma| *sigma > 0.0)
+                .with_context(|| format!("Invalid CHAOSRING_SHARPEN_SIGMA value: {}", sigma))?;
+        }
+        if let Ok(threshold) = env::var("CHAOSRING_SHARPEN_THRESHOLD") {
+            config.sharpen.threshold = threshold.parse::<i32>()
+                .with_context(|| format!("Invalid CHAOSRING_SHARPEN_THRESHOLD value: {}", threshold))?;
+        }
+        // cleared unless turned off
+        if env::var("CHAOSRING_MASK_OUTER").is_ok() {
+            config.mask.clear_outer = load_flag("CHAOSRING_MASK_OUTER")?;
+        }
+        if let Ok(feather) = env::var("CHAOSRING_MASK_FEATHER") {
+            config.mask.feather = feather.parse::<f32>().ok()
+                .filter(|feather| *feather >= 0.0)
+                .with_context(|| format!("Invalid CHAOSRING_MASK_FEATHER value: {}", feather))?;
+        }
+        if let Ok(vignette) = env::var("CHAOSRING_VIGNETTE") {
+            config.mask.vignette = vignette.parse::<f32>().ok()
+                .filter(|vignette| (0.0..=1.0).contains(vignette))
+                .with_context(|| format!("Invalid CHAOSRING_VIGNETTE value: {}", vignette))?;
+        }
+        if let Ok(frames) = env::var("CHAOSRING_REVEAL_FRAMES") {
+            config.reveal.frames = frames.parse::<u32>().ok()
+                .filter(|frames| (1..=100).contains(frames))
+                .with_context(|| format!("Invalid CHAOSRING_REVEAL_FRAMES value: {}", frames))?;
+        }
+        if let Ok(frame_ms) = env::var("CHAOSRING_REVEAL_FRAME_MS") {
+            config.reveal.frame_ms = frame_ms.parse::<u32>()
+                .with_context(|| format!("Invalid CHAOSRING_REVEAL_FRAME_MS value: {}", frame_ms))?;
+        }
+        config.event_ring = env::var("CHAOSRING_EVENT").ok().map(PathBuf::from);
+        if let Ok(message) = env::var("CHAOSRING_ACK_MESSAGE") {
+            config.ack_message = message;
+        }
+        config.allowed_types = env::var("CHAOSRING_ALLOWED_TYPES").ok()
+            .map(|types| types.split(',')
+                .map(|content_type| content_type.trim().to_ascii_lowercase())
+                .filter(|content_type| !content_type.is_empty())
+                .collect());
+        config.error_embeds = load_flag("CHAOSRING_ERROR_EMBEDS")?;
+        config.help_url = env::var("CHAOSRING_HELP_URL").ok();
+        config.role_help = env::var("CHAOSRING_ROLE_HELP").ok();
+        config.ring_others = load_flag("CHAOSRING_RING_OTHERS")?;
+        config.self_test = load_flag("CHAOSRING_SELF_TEST")?;
+        config.preview = load_flag("CHAOSRING_PREVIEW")?;
+        if let Ok(name) = env::var("CHAOSRING_FORCE_TIER") {
+            if !config.tiers.iter().any(|tier| tier.name.eq_ignore_ascii_case(&name)) {
+                anyhow::bail!("Invalid CHAOSRING_FORCE_TIER value: {}, there is no tier with this name", name);
+            }
+            warn!("CHAOSRING_FORCE_TIER is set, everyone gets the {} ring whatever their roles (for development only)", name);
+            config.force_tier = Some(name);
+        }
+        config.random_tier = load_flag("CHAOSRING_RANDOM_TIER")?;
+        if config.random_tier {
+            if config.force_tier.is_some() {
+                anyhow::bail!("CHAOSRING_RANDOM_TIER and CHAOSRING_FORCE_TIER cannot both be set");
+            }
+            warn!("CHAOSRING_RANDOM_TIER is set, everyone gets a random ring whatever their roles (for demos only)");
+        }
+        if let Ok(guilds) = env::var("CHAOSRING_ALLOWED_GUILDS") {
+            config.allowed_guilds = Some(guilds.split(',')
+                .map(str::trim)
+                .filter(|guild| !guild.is_empty())
+                .map(|guild| guild.parse::<u64>().map(GuildId)
+                    .with_context(|| format!("Invalid CHAOSRING_ALLOWED_GUILDS value: {}", guild)))
+                .collect::<anyhow::Result<Vec<GuildId>>>()?);
+        }
+        if let Ok(checks) = env::var("CHAOSRING_REGISTRATION_CHECKS") {
+            config.registration_checks = checks.parse::<u32>()
+                .with_context(|| format!("Invalid CHAOSRING_REGISTRATION_CHECKS value: {}", checks))?;
+        }
+        if env::var("CHAOSRING_COOLDOWN_BYPASS_ROLE").is_ok() {
+            config.cooldown_bypass_role = Some(load_role_id("CHAOSRING_COOLDOWN_BYPASS_ROLE")?);
+        }
+        if let Ok(guild) = env::var("CHAOSRING_DEV_GUILD") {
+            config.dev_guild = Some(guild.trim().parse::<u64>().map(GuildId)
+                .with_context(|| format!("Invalid CHAOSRING_DEV_GUILD value: {}", guild))?);
+        }
+        if let Ok(filter) = env::var("CHAOSRING_RING_FILTER") {
+            config.ring_filter = parse_filter(&filter)
+                .with_context(|| format!("Invalid CHAOSRING_RING_FILTER value: {}", filter))?;
+        }
+        if let Ok(min_band) = env::var("CHAOSRING_MIN_BAND") {
+            config.min_band = Some(min_band.parse::<u32>()
+                .with_context(|| format!("Invalid CHAOSRING_MIN_BAND value: {}", min_band))?);
+        }
+        if let Ok(millis) = env::var("CHAOSRING_SLOW_DOWNLOAD_MS") {
+            config.slow_download = Some(Duration::from_millis(millis.parse::<u64>()
+                .with_context(|| format!("Invalid CHAOSRING_SLOW_DOWNLOAD_MS value: {}", millis))?));
+        }
+        if let Ok(min_dimension) = env::var("CHAOSRING_MIN_DIMENSION") {
+            config.min_dimension = Some(min_dimension.parse::<u32>()
+                .with_context(|| format!("Invalid CHAOSRING_MIN_DIMENSION value: {}", min_dimension))?);
+        }
+        if let Ok(min_dimension) = env::var("CHAOSRING_MIN_DIMENSION_HARD") {
+            config.min_dimension_hard = Some(min_dimension.parse::<u32>()
+                .with_context(|| format!("Invalid CHAOSRING_MIN_DIMENSION_HARD value: {}", min_dimension))?);
+        }
+        check_min_dimensions(config.min_dimension, config.min_dimension_hard)?;
+        if let Ok(max_dimension) = env::var("CHAOSRING_MAX_DIMENSION") {
+            config.max_dimension = max_dimension.parse::<u32>()
+                .with_context(|| format!("Invalid CHAOSRING_MAX_DIMENSION value: {}", max_dimension))?;
+        }
+        // once the chroma key is known, rings saved without it or an alpha channel would break the sizing
+        for tier in &config.tiers {
+            validate_ring(&tier.ring, config.chroma_key.as_ref(), config.min_band)
+                .with_context(|| format!("Invalid ring for the {} tier", tier.name))?;
+            for (variant, ring) in &tier.variants {
+                validate_ring(ring, config.chroma_key.as_ref(), config.min_band)
+                    .with_context(|| format!("Invalid {} ring for the {} tier", variant, tier.name))?;
+            }
+            if let Some(font) = &tier.label_font {
+                Font::load(font).with_context(|| format!("Invalid label font for the {} tier", tier.name))?;
+            }
+        }
+        if let Some(event_ring) = &config.event_ring {
+            validate_ring(event_ring, config.chroma_key.as_ref(), config.min_band)
+                .context("Invalid CHAOSRING_EVENT ring")?;
+        }
+        if let Ok(path) = env::var("CHAOSRING_BG_IMAGE") {
+            config.background_image = Some(image::open(&path)
+                .with_context(|| format!("Invalid CHAOSRING_BG_IMAGE image {}", path))?);
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Config> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read the config file {}", path.display()))?;
+        let config = if path.extension().is_some_and(|extension| extension == "json") {
+            serde_json::from_str(&content)
+                .with_context(|| format!("Invalid config file {}", path.display()))?
+        } else {
+            toml::from_str(&content)
+                .with_context(|| format!("Invalid config file {}", path.display()))?
+        };
+        Ok(config)
+    }
+
+    /// Refuse attachments of a type outside the allowed ones, an unknown type being checked when decoding
+    pub fn check_content_type(&self, content_type: Option<&str>) -> Result<(), UserRecoverableError> {
+        let content_type = match content_type {
+            Some(content_type) => content_type.to_ascii_lowercase(),
+            None => return Ok(()),
+        };
+        // parameters like "; charset=..." are not part of the type
+        let mime_type = content_type.split(';').next().unwrap_or_default().trim();
+        let allowed = match &self.allowed_types {
+            Some(allowed_types) => allowed_types.iter().any(|allowed_type| allowed_type == mime_type),
+            None => mime_type.starts_with("image/"),
+        };
+        if allowed {
+            return Ok(());
+        }
+
+        let expected = match &self.allowed_types {
+            Some(allowed_types) => allowed_types.join(", "),
+            None => String::from("an image"),
+        };
+        Err(UserRecoverableError::new(format!("Unsupported file type {}, expected {}", mime_type, expected)))
+    }
+
+    /// Whether a member holding the roles skips the `/ring` cooldown
+    pub fn bypasses_cooldown(&self, roles: &[RoleId]) -> bool {
+        self.cooldown_bypass_role.is_some_and(|role| roles.contains(&role))
+    }
+
+    /// Whether commands are accepted from the server, direct messages being refused once servers are listed
+    pub fn is_guild_allowed(&self, guild_id: Option<GuildId>) -> bool {
+        match &self.allowed_guilds {
+            Some(allowed_guilds) => guild_id.is_some_and(|guild_id| allowed_guilds.contains(&guild_id)),
+            None => true,
+        }
+    }
+
+    fn from_env() -> anyhow::Result<Config> {
+        let tiers = ENV_TIERS.iter()
+            .map(|(name, role_variable, ring_variable)| {
+                Ok(Tier {
+                    name: name.to_string(),
+                    role_ids: vec![load_role_id(role_variable)?.0],
+                    ring: PathBuf::from(load_env_var(ring_variable)?),
+                    scale: None,
+                    format: None,
+                    label_color: None,
+                    label_font: None,
+                    variants: HashMap::new(),
+                })
+            })
+            .collect::<anyhow::Result<Vec<Tier>>>()?;
+        Ok(Config {
+            tiers,
+            jpeg_background: default_jpeg_background(),
+            preserve_format: false,
+            linear_output: false,
+            ring_filter: default_ring_filter(),
+            min_band: None,
+            slow_download: None,
+            min_dimension: None,
+            min_dimension_hard: None,
+            max_dimension: default_max_dimension(),
+            max_output_bytes: None,
+            blend_mode: BlendMode::Normal,
+            label_position: default_label_position(),
+            avatar_size: None,
+            canvas_padding: 0,
+            ring_offset: (0, 0),
+            square_tolerance: default_square_tolerance(),
+            smart_crop: false,
+            shadow: Shadow::default(),
+            chroma_key: None,
+            sharpen: Sharpen::default(),
+            reveal: Reveal::default(),
+            mask: Mask::default(),
+            background_image: None,
+            event_ring: None,
+            ack_message: default_ack_message(),
+            allowed_types: None,
+            error_embeds: false,
+            help_url: None,
+            role_help: None,
+            ring_others: false,
+            self_test: false,
+            preview: false,
+            force_tier: None,
+            random_tier: false,
+            allowed_guilds: None,
+            dev_guild: None,
+            cooldown_bypass_role: None,
+            registration_checks: 0,
+        })
+    }
+
+    /// Move the tiers named in the comma-separated `order` first, in that order, the others keeping theirs after them
+    fn reorder_tiers(&mut self, order: &str) -> anyhow::Result<()> {
+        let mut ordered = Vec::with_capacity(self.tiers.len());
+        for name in order.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let index = self.tiers.iter().position(|tier| tier.name.eq_ignore_ascii_case(name))
+                .with_context(|| format!("No tier named {}", name))?;
+            ordered.push(self.tiers.remove(index));
+        }
+        ordered.append(&mut self.tiers);
+        self.tiers = ordered;
+        Ok(())
+    }
+
+    fn apply_env_overrides(&mut self) -> anyhow::Result<()> {
+        for (name, role_variable, ring_variable) in ENV_TIERS {
+            if let Some(tier) = self.tiers.iter_mut().find(|tier| tier.name.eq_ignore_ascii_case(name)) {
+                if env::var(role_variable).is_ok() {
+                    tier.role_ids = vec![load_role_id(role_variable)?.0];
+                }
+                if let Ok(ring) = env::var(ring_variable) {
+                    tier.ring = PathBuf::from(ring);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Discord's dark theme background
+fn default_jpeg_background() -> Rgb<u8> {
+    Rgb([0x36, 0x39, 0x3f])
+}
+
+/// The ring art is the most visible part of the output so it gets a high-quality filter
+fn default_ring_filter() -> FilterType {
+    FilterType::Lanczos3
+}
+
+fn default_ack_message() -> String {
+    String::from("Preparing your avatar...")
+}
+
+fn default_square_tolerance() -> f32 {
+    0.05
+}
+
+fn default_label_position() -> LabelPosition {
+    LabelPosition::Bottom
+}
+
+/// Generous for avatars while keeping a single output well under the memory of a small host
+fn default_max_dimension() -> u32 {
+    4096
+}
+
+/// Parse the name of a resize filter
+pub fn parse_filter(value: &str) -> anyhow::Result<FilterType> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "nearest" => Ok(FilterType::Nearest),
+        "triangle" => Ok(FilterType::Triangle),
+        "catmullrom" => Ok(FilterType::CatmullRom),
+        "gaussian" => Ok(FilterType::Gaussian),
+        "lanczos3" => Ok(FilterType::Lanczos3),
+        _ => anyhow::bail!("Expected one of nearest, triangle, catmullrom, gaussian or lanczos3"),
+    }
+}
+
+pub fn parse_blend_mode(value: &str) -> anyhow::Result<BlendMode> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "normal" => Ok(BlendMode::Normal),
+        "screen" => Ok(BlendMode::Screen),
+        "add" => Ok(BlendMode::Add),
+        _ => anyhow::bail!("Expected one of normal, screen or add"),
+    }
+}
+
+/// Parse a `#rrggbb` (or `rrggbb`) colour
+pub fn parse_hex_color(value: &str) -> anyhow::Result<Rgb<u8>> {
+    let hex = value.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("Expected a colour formatted as #rrggbb");
+    }
+    let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16);
+    Ok(Rgb([channel(0)?, channel(2)?, channel(4)?]))
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Rgb<u8>>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|color| parse_hex_color(&color).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+fn deserialize_role_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RoleIdValue {
+        Number(u64),
+        Text(String),
+    }
+
+    Vec::<RoleIdValue>::deserialize(deserializer)?
+        .into_iter()
+        .map(|value| {
+            let value = match value {
+                RoleIdValue::Number(id) => id.to_string(),
+                RoleIdValue::Text(text) => text,
+            };
+            parse_role_id(value).map_err(serde::de::Error::custom)
+        })
+        .collect()
+}
+
+pub fn load_env_var(variable: &str) -> anyhow::Result<String> {
+    let var = env::var(variable)
+        .with_context(|| format!("No variable with name {} found in the environment", &variable))?;
+    Ok(var)
+}
+
+/// Read a boolean variable, unset meaning false
+pub fn load_flag(variable: &str) -> anyhow::Result<bool> {
+    match env::var(variable) {
+        Ok(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(true),
+            "0" | "false" | "no" | "off" | "" => Ok(false),
+            _ => anyhow::bail!("Expected {} to be true or false, got {}", variable, value),
+        },
+        Err(_) => Ok(false),
+    }
+}
+
+/// Refuse a hard floor above the soft one, which would never let the warning show
+fn check_min_dimensions(soft: Option<u32>, hard: Option<u32>) -> anyhow::Result<()> {
+    if let (Some(soft), Some(hard)) = (soft, hard) {
+        if hard > soft {
+            anyhow::bail!("CHAOSRING_MIN_DIMENSION_HARD ({}) must not be above CHAOSRING_MIN_DIMENSION ({})", hard, soft);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the id has a timestamp past the Discord epoch, snowflakes keep it above their 22 lower bits
+fn is_plausible_snowflake(id: u64) -> bool {
+    id >> 22 > 0
+}
+
+/// A warning for each role id of the tiers too small to be a Discord id, likely a copy-paste error
+fn implausible_role_ids(tiers: &[Tier]) -> Vec<String> {
+    tiers.iter()
+        .flat_map(|tier| tier.role_ids.iter()
+            .filter(|role_id| !is_plausible_snowflake(**role_id))
+            .map(move |role_id| format!("The role id {} of the {} tier is too small to be a Discord id", role_id, tier.name)))
+        .collect()
+}
+
+/// Parse a role id, either bare or as pasted from a role mention (`<@&id>`)
+fn parse_role_id(value: String) -> anyhow::Result<u64> {
+    let trimmed = value.trim();
+    let id = trimmed.strip_prefix("<@&")
+        .and_then(|mention| mention.strip_suffix('>'))
+        .unwrap_or(trimmed);
+    let id = id.parse::<u64>()
+        .with_context(|| format!("Expected a role id or a role mention, got {}", value))?;
+    Ok(id)
+}
+
+pub fn load_role_id(variable: &str) -> anyhow::Result<RoleId> {
+    let role_id = load_env_var(variable)
+        .and_then(parse_role_id)?;
+    Ok(RoleId(role_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_config() -> Config {
+        toml::from_str("tiers = []").unwrap()
+    }
+
+    #[test]
+    fn only_the_bypass_role_skips_the_cooldown() {
+        assert!(!default_config().bypasses_cooldown(&[RoleId(1)]));
+
+        let config = Config { cooldown_bypass_role: Some(RoleId(1)), ..default_config() };
+        assert!(config.bypasses_cooldown(&[RoleId(2), RoleId(1)]));
+        assert!(!config.bypasses_cooldown(&[RoleId(2)]));
+        assert!(!config.bypasses_cooldown(&[]));
+    }
+
+    #[test]
+    fn loads_the_sample_config_file() {
+        let config = Config::from_file(&Path::new(env!("CARGO_MANIFEST_DIR")).join("config.sample.toml")).unwrap();
+        let names = config.tiers.iter().map(|tier| tier.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["DAOist", "Regular", "Fren"]);
+        assert_eq!(config.tiers[0].label_color, Some(Rgb([0xff, 0xd7, 0x00])));
+        assert_eq!(config.tiers[0].variants["holiday"], PathBuf::from("./chaosDAO-DaoistRing-holiday.png"));
+        assert_eq!(config.tiers[2].scale, Some(0.5));
+        assert_eq!(config.tiers[2].format, Some(OutputFormat::Jpeg));
+        // the settings outside the file keep their defaults
+        assert_eq!(config.max_dimension, 4096);
+    }
+
+    #[test]
+    fn loads_a_json_config_file() {
+        let path = env::temp_dir().join(format!("chaosring-config-{}.json", std::process::id()));
+        fs::write(&path, r#"{"tiers": [{"name": "DAOist", "role_ids": [1234567890], "ring": "ring.png"}]}"#).unwrap();
+        let config = Config::from_file(&path).unwrap();
+        assert_eq!(config.tiers[0].role_ids, vec![1234567890]);
+        assert_eq!(config.tiers[0].ring, PathBuf::from("ring.png"));
+    }
+
+    #[test]
+    fn parses_hex_colours() {
+        assert_eq!(parse_hex_color("#36393f").unwrap(), Rgb([0x36, 0x39, 0x3f]));
+        assert_eq!(parse_hex_color(" FFD700 ").unwrap(), Rgb([0xff, 0xd7, 0x00]));
+        assert!(parse_hex_color("#fff").is_err());
+        assert!(parse_hex_color("#gggggg").is_err());
+    }
+
+    #[test]
+    fn parses_plain_padded_and_mentioned_role_ids() {
+        assert_eq!(parse_role_id(String::from("1234567890")).unwrap(), 1234567890);
+        assert_eq!(parse_role_id(String::from("  1234567890\n")).unwrap(), 1234567890);
+        assert_eq!(parse_role_id(String::from("<@&1234567890>")).unwrap(), 1234567890);
+        assert!(parse_role_id(String::from("<@1234567890>")).is_err());
+        assert!(parse_role_id(String::from("DAOist")).is_err());
+    }
+
+    #[test]
+    fn parses_the_role_ids_of_the_config_file_like_the_variables() {
+        let tiers = |role_ids: &str| toml::from_str::<Config>(&format!("[[tiers]]\nname = \"DAOist\"\nring = \"ring.png\"\nrole_ids = {}", role_ids));
+        assert_eq!(tiers(r#"[1234567890, " <@&987654321> "]"#).unwrap().tiers[0].role_ids, vec![1234567890, 987654321]);
+        assert!(tiers(r#"["DAOist"]"#).is_err());
+        assert!(tiers("[-1]").is_err());
+    }
+
+    #[test]
+    fn warns_about_role_ids_too_small_to_be_snowflakes() {
+        let config: Config = toml::from_str(r#"
+            [[tiers]]
+            name = "DAOist"
+            role_ids = [1, 1039221924317368340]
+            ring = "ring.png"
+        "#).unwrap();
+        assert_eq!(implausible_role_ids(&config.tiers), vec!["The role id 1 of the DAOist tier is too small to be a Discord id"]);
+    }
+
+    #[test]
+    fn accepts_any_image_type_by_default() {
+        let config = default_config();
+        assert!(config.check_content_type(Some("image/png")).is_ok());
+        assert!(config.check_content_type(Some("image/webp; charset=binary")).is_ok());
+        assert!(config.check_content_type(None).is_ok());
+        assert!(config.check_content_type(Some("application/pdf")).is_err());
+    }
+
+    #[test]
+    fn accepts_only_the_allowed_types() {
+        let config = Config { allowed_types: Some(vec![String::from("image/png")]), ..default_config() };
+        assert!(config.check_content_type(Some("IMAGE/PNG")).is_ok());
+        let err = config.check_content_type(Some("image/gif")).unwrap_err();
+        assert_eq!(err.to_string(), "Error while preparing an avatar: Unsupported file type image/gif, expected image/png");
+    }
+
+    #[test]
+    fn accepts_only_the_allowed_guilds() {
+        assert!(default_config().is_guild_allowed(None));
+        assert!(default_config().is_guild_allowed(Some(GuildId(1))));
+
+        let config = Config { allowed_guilds: Some(vec![GuildId(1)]), ..default_config() };
+        assert!(config.is_guild_allowed(Some(GuildId(1))));
+        assert!(!config.is_guild_allowed(Some(GuildId(2))));
+        assert!(!config.is_guild_allowed(None));
+    }
+
+    #[test]
+    fn reordering_the_tiers_changes_the_tier_of_a_member_with_several_roles() {
+        let mut config: Config = toml::from_str(r#"
+            tiers = [
+                { name = "DAOist", role_ids = [1], ring = "daoist.png" },
+                { name = "Regular", role_ids = [2], ring = "regular.png" },
+                { name = "Fren", role_ids = [3], ring = "fren.png" },
+            ]
+        "#).unwrap();
+        let roles = [RoleId(3), RoleId(2)];
+        let tier = |config: &Config| crate::commands::ring::find_dao_role(&config.tiers, &roles, None).unwrap().name.clone();
+        assert_eq!(tier(&config), "Regular");
+
+        config.reorder_tiers("fren, daoist").unwrap();
+        let names = config.tiers.iter().map(|tier| tier.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["Fren", "DAOist", "Regular"]);
+        assert_eq!(tier(&config), "Fren");
+        assert_eq!(config.reorder_tiers("Whale").unwrap_err().to_string(), "No tier named Whale");
+    }
+
+    #[test]
+    fn refuses_a_hard_floor_above_the_soft_one() {
+        assert!(check_min_dimensions(Some(256), Some(64)).is_ok());
+        assert!(check_min_dimensions(Some(256), Some(256)).is_ok());
+        assert!(check_min_dimensions(None, Some(512)).is_ok());
+        assert_eq!(check_min_dimensions(Some(256), Some(512)).unwrap_err().to_string(),
+            "CHAOSRING_MIN_DIMENSION_HARD (512) must not be above CHAOSRING_MIN_DIMENSION (256)");
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serenity::model::id::{GuildId, UserId};
+
+/// Per-user cooldown between two `/ring` invocations
+pub struct Cooldown {
+    period: Duration,
+    last_used: Mutex<HashMap<UserId, Instant>>,
+}
+
+impl Cooldown {
+    pub fn new(period: Duration) -> Self {
+        Cooldown { period, last_used: Mutex::new(HashMap::new()) }
+    }
+
+    /// Return the remaining wait if the user is still cooling down since their last recorded use
+    pub fn check(&self, user: UserId) -> Result<(), Duration> {
+        if self.period.is_zero() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut last_used = self.last_used.lock().unwrap();
+        // forget users whose cooldown has expired so the map doesn't grow unbounded
+        last_used.retain(|_, used_at| now.duration_since(*used_at) < self.period);
+
+        match last_used.get(&user) {
+            Some(used_at) => Err(self.period - now.duration_since(*used_at)),
+            None => Ok(()),
+        }
+    }
+
+    /// Start the cooldown of the user, once their avatar is rung so that failed attempts can be retried at once
+    pub fn record(&self, user: UserId) {
+        if !self.period.is_zero() {
+            self.last_used.lock().unwrap().insert(user, Instant::now());
+        }
+    }
+}
+
+/// Token bucket per server, keeping a busy server from monopolizing the bot
+pub struct GuildRateLimit {
+    /// Commands that can be run at once
+    burst: f64,
+    /// Tokens added back per second
+    refill_per_sec: f64,
+    buckets: Mutex<HashMap<GuildId, (f64, Instant)>>,
+}
+
+impl GuildRateLimit {
+    /// Allow `per_minute` commands per minute and server, up to `burst` at once; disabled when `per_minute` is 0
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        GuildRateLimit {
+            burst: burst.max(1) as f64,
+            refill_per_sec: per_minute as f64 / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token of the server, returning false when it has none left
+    pub fn check(&self, guild: GuildId) -> bool {
+        if self.refill_per_sec == 0.0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        // forget servers whose bucket has refilled so the map doesn't grow unbounded
+        let (burst, refill_per_sec) = (self.burst, self.refill_per_sec);
+        buckets.retain(|_, (tokens, updated_at)| *tokens + now.duration_since(*updated_at).as_secs_f64() * refill_per_sec < burst);
+
+        let (tokens, updated_at) = buckets.entry(guild).or_insert((burst, now));
+        *tokens = (*tokens + now.duration_since(*updated_at).as_secs_f64() * refill_per_sec).min(burst);
+        *updated_at = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cooldown_refuses_a_second_use_by_the_same_user() {
+        let cooldown = Cooldown::new(Duration::from_secs(60));
+        assert_eq!(cooldown.check(UserId(1)), Ok(()));
+        cooldown.record(UserId(1));
+        let remaining = cooldown.check(UserId(1)).unwrap_err();
+        assert!(remaining > Duration::from_secs(59) && remaining <= Duration::from_secs(60));
+        assert_eq!(cooldown.check(UserId(2)), Ok(()));
+    }
+
+    #[test]
+    fn cooldown_of_zero_is_disabled() {
+        let cooldown = Cooldown::new(Duration::ZERO);
+        assert_eq!(cooldown.check(UserId(1)), Ok(()));
+        cooldown.record(UserId(1));
+        assert_eq!(cooldown.check(UserId(1)), Ok(()));
+    }
+
+    #[test]
+    fn cooldown_starts_only_once_recorded() {
+        let cooldown = Cooldown::new(Duration::from_secs(60));
+        // a failed attempt is not recorded, the user can try again at once
+        assert_eq!(cooldown.check(UserId(1)), Ok(()));
+        assert_eq!(cooldown.check(UserId(1)), Ok(()));
+        cooldown.record(UserId(1));
+        assert!(cooldown.check(UserId(1)).is_err());
+    }
+
+    #[test]
+    fn guild_rate_limit_allows_a_burst_per_server() {
+        let rate_limit = GuildRateLimit::new(6, 3);
+        assert!((0..3).all(|_| rate_limit.check(GuildId(1))));
+        assert!(!rate_limit.check(GuildId(1)));
+        assert!(rate_limit.check(GuildId(2)));
+    }
+
+    #[test]
+    fn guild_rate_limit_of_zero_is_disabled() {
+        let rate_limit = GuildRateLimit::new(0, 0);
+        assert!((0..100).all(|_| rate_limit.check(GuildId(1))));
+    }
+}
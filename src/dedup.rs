@@ -0,0 +1,38 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serenity::model::id::InteractionId;
+
+/// Upper bound of remembered interactions, the oldest are forgotten first
+const MAX_REMEMBERED: usize = 1024;
+
+/// Interactions received recently, to ignore the duplicate deliveries of the same interaction
+pub struct RecentInteractions {
+    window: Duration,
+    seen: Mutex<VecDeque<(InteractionId, Instant)>>,
+}
+
+impl RecentInteractions {
+    pub fn new(window: Duration) -> Self {
+        RecentInteractions { window, seen: Mutex::new(VecDeque::new()) }
+    }
+
+    /// Remember the interaction, returning false if it was already seen within the window
+    pub fn first_seen(&self, id: InteractionId) -> bool {
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+        while seen.front().is_some_and(|(_, seen_at)| now.duration_since(*seen_at) >= self.window) {
+            seen.pop_front();
+        }
+
+        if seen.iter().any(|(seen_id, _)| *seen_id == id) {
+            return false;
+        }
+        if seen.len() >= MAX_REMEMBERED {
+            seen.pop_front();
+        }
+        seen.push_back((id, now));
+        true
+    }
+}
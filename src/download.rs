@@ -0,0 +1,196 @@
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use serenity::async_trait;
+use serenity::model::prelude::Attachment;
+
+use crate::commands::ring::UserRecoverableError;
+
+const DEFAULT_MAX_DOWNLOAD_BYTES: usize = 8 * 1024 * 1024;
+/// Redirects followed from a user-provided URL, each target being checked like the URL itself
+const MAX_REDIRECTS: usize = 3;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Limit of a whole download, body included, so that a slow server cannot hold the command
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Where the user's avatar comes from
+#[async_trait]
+pub trait AvatarSource: Sync {
+    async fn download(&self) -> anyhow::Result<Vec<u8>>;
+}
+
+#[async_trait]
+impl AvatarSource for Attachment {
+    async fn download(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(Attachment::download(self).await?)
+    }
+}
+
+/// Image bytes already in memory, such as a canned avatar
+#[async_trait]
+impl AvatarSource for Vec<u8> {
+    async fn download(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(self.clone())
+    }
+}
+
+/// An image link pasted by the user
+pub struct ImageUrl<'a>(pub &'a str);
+
+#[async_trait]
+impl AvatarSource for ImageUrl<'_> {
+    async fn download(&self) -> anyhow::Result<Vec<u8>> {
+        download_image(self.0, max_download_bytes()?).await
+    }
+}
+
+/// Largest image accepted from a link or a webhook request
+pub fn max_download_bytes() -> anyhow::Result<usize> {
+    match env::var("CHAOSRING_MAX_DOWNLOAD_BYTES") {
+        Ok(value) => value.parse::<usize>()
+            .with_context(|| format!("Invalid CHAOSRING_MAX_DOWNLOAD_BYTES value: {}", value)),
+        Err(_) => Ok(DEFAULT_MAX_DOWNLOAD_BYTES),
+    }
+}
+
+/// Download an image from a user-provided http(s) URL, refusing anything larger than `max_bytes`
+pub async fn download_image(url: &str, max_bytes: usize) -> anyhow::Result<Vec<u8>> {
+    let mut url = Url::parse(url)
+        .map_err(|_| UserRecoverableError::new("The provided URL is not valid"))?;
+    let mut redirects = 0;
+    let response = loop {
+        let response = public_client(&url).await?.get(url.clone()).send().await
+            .map_err(|err| UserRecoverableError::new(format!("Could not download the image ({})", err)))?;
+        if !response.status().is_redirection() {
+            break response;
+        }
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            return Err(UserRecoverableError::new("The provided URL redirects too many times").into());
+        }
+        let location = response.headers().get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| UserRecoverableError::new("The provided URL redirects nowhere"))?;
+        url = url.join(location)
+            .map_err(|_| UserRecoverableError::new("The provided URL redirects to an invalid URL"))?;
+    };
+    let mut response = response.error_for_status()
+        .map_err(|err| UserRecoverableError::new(format!("Could not download the image ({})", err)))?;
+
+    let is_image = response.headers().get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(|content_type| content_type.starts_with("image/"))
+        .unwrap_or(false);
+    if !is_image {
+        return Err(UserRecoverableError::new("The provided URL does not point to an image").into());
+    }
+
+    let too_large = || UserRecoverableError::new(format!("The image is larger than {} bytes", max_bytes));
+    if response.content_length().is_some_and(|length| length > max_bytes as u64) {
+        return Err(too_large().into());
+    }
+
+    // the server may not send (or may lie about) the content length, so enforce the cap while reading
+    let mut bytes = Vec::new();
+    // the timeout covers the body too, a server stalling halfway is reported like one refusing the connection
+    let failed = |err: reqwest::Error| UserRecoverableError::new(format!("Could not download the image ({})", err));
+    while let Some(chunk) = response.chunk().await.map_err(failed)? {
+        if bytes.len() + chunk.len() > max_bytes {
+            return Err(too_large().into());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+/// A client for the http(s) URL connecting only to the public address its host resolves to, so that users cannot
+/// reach the services of the bot's network (like the cloud metadata endpoint or the webhook). Redirects are left
+/// to the caller, which checks them the same way.
+async fn public_client(url: &Url) -> anyhow::Result<reqwest::Client> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(UserRecoverableError::new("Only http(s) URLs are supported").into());
+    }
+    let port = url.port_or_known_default().unwrap_or(443);
+    let builder = reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(DOWNLOAD_TIMEOUT)
+        .redirect(Policy::none());
+    let not_public = || UserRecoverableError::new("The provided URL does not point to a public address");
+    let host = url.host_str().ok_or_else(not_public)?;
+    // IPv6 hosts are bracketed in URLs
+    let builder = match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) if is_public(ip) => builder,
+        Ok(_) => return Err(not_public().into()),
+        Err(_) => {
+            let domain = host;
+            let addresses = tokio::net::lookup_host((domain, port)).await
+                .map_err(|_| UserRecoverableError::new(format!("Cannot resolve {}", domain)))?
+                .collect::<Vec<SocketAddr>>();
+            if addresses.is_empty() || !addresses.iter().all(|address| is_public(address.ip())) {
+                return Err(not_public().into());
+            }
+            // connect to the checked address rather than resolving the name again
+            builder.resolve(domain, addresses[0])
+        }
+    };
+    Ok(builder.build()?)
+}
+
+/// Whether the address is reachable on the internet, rather than loopback, private, link-local or reserved
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    // 100.64.0.0/10 is the shared address space of carrier-grade NAT
+    let shared = a == 100 && (64..128).contains(&b);
+    !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast()
+        || ip.is_documentation() || ip.is_multicast() || shared || a == 0 || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    // 64:ff9b::/96 (NAT64) and 2002::/16 (6to4) reach the IPv4 address they embed, which must be public as well
+    let embedded = |high: u16, low: u16| Ipv4Addr::new((high >> 8) as u8, high as u8, (low >> 8) as u8, low as u8);
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return is_public_v4(embedded(segments[6], segments[7]));
+    }
+    if segments[0] == 0x2002 {
+        return is_public_v4(embedded(segments[1], segments[2]));
+    }
+    // fc00::/7 are unique local addresses and fe80::/10 link-local ones
+    let unique_local = segments[0] & 0xfe00 == 0xfc00;
+    let link_local = segments[0] & 0xffc0 == 0xfe80;
+    !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || unique_local || link_local)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_addresses_outside_the_internet() {
+        let refused = ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0",
+            "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1", "64:ff9b::a9fe:a9fe", "64:ff9b::7f00:1", "2002:a00:1::1",
+            "2002:7f00:1::"];
+        for address in refused {
+            assert!(!is_public(address.parse().unwrap()), "{} is not public", address);
+        }
+        for address in ["162.159.128.233", "2606:4700::6810:84e5", "64:ff9b::a29f:80e9", "2002:a29f:80e9::1"] {
+            assert!(is_public(address.parse().unwrap()), "{} is public", address);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use image::{Rgb, Rgba, RgbaImage};
+
+use crate::config::LabelPosition;
+
+/// Bundled 5x7 bitmap font, one row per byte with the leftmost pixel in the 5th bit
+const GLYPHS: [(char, [u8; 7]); 37] = [
+    ('A', [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001]),
+    ('B', [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110]),
+    ('C', [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110]),
+    ('D', [0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110]),
+    ('E', [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111]),
+    ('F', [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000]),
+    ('G', [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111]),
+    ('H', [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001]),
+    ('I', [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('J', [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100]),
+    ('K', [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001]),
+    ('L', [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111]),
+    ('M', [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001]),
+    ('N', [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001]),
+    ('O', [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110]),
+    ('P', [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000]),
+    ('Q', [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101]),
+    ('R', [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001]),
+    ('S', [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110]),
+    ('T', [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100]),
+    ('U', [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110]),
+    ('V', [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100]),
+    ('W', [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010]),
+    ('X', [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001]),
+    ('Y', [0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100]),
+    ('Z', [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111]),
+    ('0', [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110]),
+    ('1', [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('2', [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111]),
+    ('3', [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110]),
+    ('4', [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010]),
+    ('5', [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110]),
+    ('6', [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110]),
+    ('7', [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000]),
+    ('8', [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110]),
+    ('9', [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100]),
+    ('-', [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000]),
+];
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+/// Monospaced bitmap font, one row of bits per line of a glyph with the leftmost pixel in the highest bit
+pub struct Font {
+    width: u32,
+    height: u32,
+    glyphs: HashMap<char, Vec<u32>>,
+}
+
+impl Font {
+    /// The bundled font, capitals, digits and dash only
+    pub fn bundled() -> Self {
+        let glyphs = GLYPHS.iter()
+            .map(|(c, rows)| (*c, rows.iter().map(|row| *row as u32).collect()))
+            .collect();
+        Font { width: GLYPH_WIDTH, height: GLYPH_HEIGHT, glyphs }
+    }
+
+    /// Load a font in the BDF format of X11 bitmap fonts, every glyph being drawn in a cell of the size of
+    /// the font bounding box
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read the font {}", path.display()))?;
+        parse_bdf(&content).with_context(|| format!("Invalid BDF font {}", path.display()))
+    }
+
+    /// The glyph of the character, or of its capital in fonts without small letters
+    fn glyph(&self, c: char) -> Option<&[u32]> {
+        self.glyphs.get(&c)
+            .or_else(|| c.to_uppercase().next().and_then(|capital| self.glyphs.get(&capital)))
+            .map(Vec::as_slice)
+    }
+}
+
+/// Parse the glyphs of a BDF font, placed in their cell from their bounding box relative to the baseline
+fn parse_bdf(content: &str) -> anyhow::Result<Font> {
+    fn numbers<const N: usize>(line: &str) -> anyhow::Result<[i32; N]> {
+        let numbers = line.split_whitespace().skip(1)
+            .map(|number| number.parse::<i32>().with_context(|| format!("Invalid number in {}", line)))
+            .collect::<anyhow::Result<Vec<i32>>>()?;
+        numbers.try_into().map_err(|_| anyhow::anyhow!("Expected {} numbers in {}", N, line))
+    }
+
+    let mut bounds: Option<[i32; 4]> = None;
+    let mut glyphs = HashMap::new();
+    let mut encoding = None;
+    let mut glyph_bounds = None;
+    let mut lines = content.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        match line.split_whitespace().next().unwrap_or_default() {
+            "FONTBOUNDINGBOX" => bounds = Some(numbers::<4>(line)?),
+            "ENCODING" => encoding = u32::try_from(numbers::<1>(line)?[0]).ok().and_then(char::from_u32),
+            "BBX" => glyph_bounds = Some(numbers::<4>(line)?),
+            "BITMAP" => {
+                let [width, height, x, y] = bounds.context("Missing FONTBOUNDINGBOX")?;
+                if !(1..=32).contains(&width) || height < 1 {
+                    anyhow::bail!("Unsupported font size {}x{}", width, height);
+                }
+                let [glyph_width, glyph_height, glyph_x, glyph_y] = glyph_bounds.take().context("Missing BBX")?;
+                let mut rows = vec![0u32; height as usize];
+                // from the top of the cell: the ascent of the font above the top of the glyph
+                let top = (height + y) - (glyph_y + glyph_height);
+                for row in 0..glyph_height {
+                    let hex = lines.next().context("Missing BITMAP rows")?;
+                    let bits = u64::from_str_radix(hex, 16).with_context(|| format!("Invalid BITMAP row {}", hex))?;
+                    let cell_row = top + row;
+                    for column in 0..glyph_width {
+                        let cell_column = glyph_x - x + column;
+                        let set = bits & (1 << (hex.len() as i32 * 4 - 1 - column)) != 0;
+                        if set && (0..height).contains(&cell_row) && (0..width).contains(&cell_column) {
+                            rows[cell_row as usize] |= 1 << (width - 1 - cell_column);
+                        }
+                    }
+                }
+                if let Some(c) = encoding.take() {
+                    glyphs.insert(c, rows);
+                }
+            }
+            _ => {}
+        }
+    }
+    let [width, height, _, _] = bounds.context("Missing FONTBOUNDINGBOX")?;
+    if glyphs.is_empty() {
+        anyhow::bail!("No glyphs");
+    }
+    Ok(Font { width: width as u32, height: height as u32, glyphs })
+}
+
+/// Draw `text` (in capitals when the font has no small letters, unknown characters are blank) in a badge
+/// centered on the ring band.
+/// The badge is dark over a bright ring and bright over a dark one, unless the text has a `color`
+/// in which case the badge contrasts with it.
+/// Returns false, leaving the image untouched, when there is no text or the badge doesn't fit.
+pub fn draw_label(image: &mut RgbaImage, font: &Font, text: &str, position: LabelPosition, ring_width: u32, color: Option<Rgb<u8>>) -> bool {
+    let chars = text.chars().count() as u32;
+    if chars == 0 {
+        return false;
+    }
+
+    let side = image.width();
+    let scale = (side / 128).max(1);
+    let padding = 2 * scale;
+    let badge_width = (chars * (font.width + 1) - 1) * scale + 2 * padding;
+    let badge_height = font.height * scale + 2 * padding;
+    if badge_width > side || badge_height > image.height() {
+        return false;
+    }
+
+    let band_center = ring_width / 2;
+    let center_y = match position {
+        LabelPosition::Top => band_center,
+        LabelPosition::Bottom => image.height().saturating_sub(band_center),
+    };
+    let left = (side - badge_width) / 2;
+    let top = center_y.saturating_sub(badge_height / 2).min(image.height() - badge_height);
+
+    let (background, foreground) = match color {
+        Some(Rgb([r, g, b])) if luminance(r, g, b) > 127.0 => (Rgba([0, 0, 0, 255]), Rgba([r, g, b, 255])),
+        Some(Rgb([r, g, b])) => (Rgba([255, 255, 255, 255]), Rgba([r, g, b, 255])),
+        None if mean_luminance(image, left, top, badge_width, badge_height) > 127.0 => {
+            (Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255]))
+        }
+        None => (Rgba([255, 255, 255, 255]), Rgba([0, 0, 0, 255])),
+    };
+
+    for y in top..top + badge_height {
+        for x in left..left + badge_width {
+            image.put_pixel(x, y, background);
+        }
+    }
+
+    for (index, c) in text.chars().enumerate() {
+        let rows = match font.glyph(c) {
+            Some(rows) => rows,
+            None => continue,
+        };
+        let glyph_left = left + padding + index as u32 * (font.width + 1) * scale;
+        for (row, bits) in rows.iter().enumerate() {
+            for column in 0..font.width {
+                if bits & (1 << (font.width - 1 - column)) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = glyph_left + column * scale + dx;
+                        let y = top + padding + row as u32 * scale + dy;
+                        image.put_pixel(x, y, foreground);
+                    }
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Average luminance of the opaque pixels in the area, 0 when there are none
+fn mean_luminance(image: &RgbaImage, left: u32, top: u32, width: u32, height: u32) -> f32 {
+    let (sum, count) = (top..top + height)
+        .flat_map(|y| (left..left + width).map(move |x| (x, y)))
+        .map(|(x, y)| image.get_pixel(x, y))
+        .filter(|px| px[3] != 0)
+        .fold((0.0, 0u32), |(sum, count), px| (sum + luminance(px[0], px[1], px[2]), count + 1));
+    if count == 0 { 0.0 } else { sum / count as f32 }
+}
+
+fn luminance(r: u8, g: u8, b: u8) -> f32 {
+    0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x4 font with a descent of 1 and a small `a` of 3x3 sitting on the baseline
+    const FONT: &str = "STARTFONT 2.1
+FONT test
+SIZE 4 75 75
+FONTBOUNDINGBOX 3 4 0 -1
+CHARS 1
+STARTCHAR a
+ENCODING 97
+BBX 3 3 0 0
+BITMAP
+E0
+A0
+E0
+ENDCHAR
+ENDFONT
+";
+
+    #[test]
+    fn parses_a_bdf_font() {
+        let font = parse_bdf(FONT).unwrap();
+        assert_eq!((font.width, font.height), (3, 4));
+        assert_eq!(font.glyph('a'), Some(&[0b111, 0b101, 0b111, 0b000][..]));
+        assert_eq!(font.glyph('b'), None);
+        assert!(parse_bdf("STARTFONT 2.1\nENDFONT\n").is_err());
+    }
+
+    #[test]
+    fn the_bundled_font_draws_small_letters_in_capitals() {
+        let font = Font::bundled();
+        assert_eq!(font.glyph('d'), font.glyph('D'));
+        assert!(font.glyph('d').is_some());
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Deserialize;
+use serenity::builder::CreateApplicationCommand;
+use tracing::info;
+
+use crate::registration;
+
+/// Locales supported by Discord, see https://discord.com/developers/docs/reference#locales
+const DISCORD_LOCALES: [&str; 31] = [
+    "id", "da", "de", "en-GB", "en-US", "es-ES", "fr", "hr", "it", "lt", "hu", "nl", "no", "pl", "pt-BR", "ro",
+    "fi", "sv-SE", "vi", "tr", "cs", "el", "bg", "ru", "uk", "hi", "th", "zh-CN", "ja", "zh-TW", "ko",
+];
+const MAX_NAME_LENGTH: usize = 32;
+const MAX_DESCRIPTION_LENGTH: usize = 100;
+
+/// Translation of a command, what is left out being shown in English
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Translation {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Translations of the command names and descriptions, by locale then by command name
+#[derive(Debug, Default)]
+pub struct Locales(HashMap<String, HashMap<String, Translation>>);
+
+impl Locales {
+    /// The translations of the JSON file in `CHAOSRING_LOCALE_FILE`, none when unset
+    pub fn from_env() -> anyhow::Result<Self> {
+        match env::var("CHAOSRING_LOCALE_FILE") {
+            Ok(path) => Locales::load(Path::new(&path)),
+            Err(_) => Ok(Locales::default()),
+        }
+    }
+
+    /// The translations of the JSON file, checked against the limits of Discord
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read the locale file {}", path.display()))?;
+        let translations = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid locale file {}", path.display()))?;
+        let locales = Locales(translations);
+        locales.validate().with_context(|| format!("Invalid locale file {}", path.display()))?;
+        info!("Loaded command translations for: {:?}", locales.0.keys().collect::<Vec<&String>>());
+        Ok(locales)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Add the translations of the command to its definition
+    pub fn localize<'a>(&self, command: &'a mut CreateApplicationCommand) -> &'a mut CreateApplicationCommand {
+        let name = command.0.get("name").and_then(|name| name.as_str()).unwrap_or_default().to_string();
+        // context menu commands have no description to translate
+        let has_description = command.0.contains_key("description");
+        for (locale, commands) in &self.0 {
+            let translation = match commands.get(&name) {
+                Some(translation) => translation,
+                None => continue,
+            };
+            if let Some(localized_name) = &translation.name {
+                command.name_localized(locale, localized_name);
+            }
+            if let Some(description) = translation.description.as_ref().filter(|_| has_description) {
+                command.description_localized(locale, description);
+            }
+        }
+        command
+    }
+
+    /// Check the locales and the translations against the limits of Discord, which would refuse the whole registration
+    fn validate(&self) -> anyhow::Result<()> {
+        let definitions = registration::definitions(&Locales::default());
+        for (locale, commands) in &self.0 {
+            if !DISCORD_LOCALES.contains(&locale.as_str()) {
+                anyhow::bail!("Unknown locale {}, expected one of {}", locale, DISCORD_LOCALES.join(", "));
+            }
+            for (command, translation) in commands {
+                let definition = definitions.iter()
+                    .find(|definition| definition["name"].as_str() == Some(command.as_str()))
+                    .with_context(|| format!("Unknown command {} in the {} translations", command, locale))?;
+                // chat input commands are the default type and need lowercase names without spaces
+                let chat_input = definition["type"].as_u64().unwrap_or(1) == 1;
+                if let Some(name) = &translation.name {
+                    let length = name.chars().count();
+                    if length == 0 || length > MAX_NAME_LENGTH {
+                        anyhow::bail!("The {} name of {} must have between 1 and {} characters", locale, command, MAX_NAME_LENGTH);
+                    }
+                    if chat_input && name.chars().any(|c| c.is_whitespace() || c.is_uppercase()) {
+                        anyhow::bail!("The {} name of {} must be lowercase without spaces", locale, command);
+                    }
+                }
+                if let Some(description) = &translation.description {
+                    if !chat_input {
+                        anyhow::bail!("The {} command has no description to translate", command);
+                    }
+                    let length = description.chars().count();
+                    if length == 0 || length > MAX_DESCRIPTION_LENGTH {
+                        anyhow::bail!("The {} description of {} must have between 1 and {} characters", locale, command, MAX_DESCRIPTION_LENGTH);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locale_file(name: &str, content: &str) -> std::path::PathBuf {
+        let path = env::temp_dir().join(format!("chaosring-locales-{}-{}.json", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn registers_the_translated_names_and_descriptions() {
+        let path = locale_file("fr", r#"{"fr": {"ring": {"name": "anneau", "description": "Ajoute l'anneau à ton avatar"}}}"#);
+        let locales = Locales::load(&path).unwrap();
+        let definitions = registration::definitions(&locales);
+        let ring = definitions.iter().find(|definition| definition["name"] == "ring").unwrap();
+        assert_eq!(ring["name_localizations"]["fr"], "anneau");
+        assert_eq!(ring["description_localizations"]["fr"], "Ajoute l'anneau à ton avatar");
+        // the untranslated commands keep their English text only
+        let status = definitions.iter().find(|definition| definition["name"] == "ring-status").unwrap();
+        assert!(status.get("name_localizations").is_none());
+    }
+
+    #[test]
+    fn refuses_unknown_locales_and_invalid_names() {
+        let path = locale_file("klingon", r#"{"tlh": {"ring": {"name": "ring"}}}"#);
+        assert!(Locales::load(&path).unwrap_err().chain().any(|err| err.to_string().contains("Unknown locale tlh")));
+        let path = locale_file("spaces", r#"{"de": {"ring": {"name": "Ring Avatar"}}}"#);
+        assert!(Locales::load(&path).unwrap_err().chain().any(|err| err.to_string().contains("lowercase without spaces")));
+    }
+}
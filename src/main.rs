@@ -1,81 +1,782 @@
+use std::borrow::Cow;
 use std::env;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
+use serde_json::{json, Value};
 use serenity::async_trait;
+use serenity::builder::CreateComponents;
+use serenity::client::bridge::gateway::event::ShardStageUpdateEvent;
+use serenity::http::HttpError;
+use serenity::http::multipart::Multipart;
+use serenity::http::request::RequestBuilder;
+use serenity::http::routing::RouteInfo;
 use serenity::model::application::interaction::{Interaction, InteractionResponseType};
+use serenity::model::application::component::ButtonStyle;
 use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::event::ResumedEvent;
 use serenity::model::gateway::Ready;
-use serenity::model::prelude::AttachmentType;
-use serenity::model::prelude::command::Command;
+use serenity::model::guild::Member;
+use serenity::model::prelude::{Attachment, AttachmentType, Message, RoleId, User};
+use serenity::model::Timestamp;
 use serenity::model::prelude::interaction::application_command::CommandDataOptionValue;
 use serenity::prelude::*;
+use serenity::utils::Colour;
+use tracing::{error, info, Subscriber, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
 
-use crate::commands::ring::UserRecoverableError;
+use crate::cache::AvatarCache;
+use crate::commands::ring::{AvatarStyle, PendingRing, RingedAvatar, RingOptions, UserRecoverableError};
+use crate::commands::ring_team::Split;
+use crate::config::{Config, parse_blend_mode, parse_hex_color};
+use crate::cooldown::{Cooldown, GuildRateLimit};
+use crate::dedup::RecentInteractions;
+use crate::download::{AvatarSource, ImageUrl};
+use crate::locales::Locales;
+use crate::moderation::{Classifier, Moderated};
+use crate::preferences::{PreferenceStore, Preferences};
+use crate::public_posts::{PostedFile, PublicPosts};
+use crate::upload::Uploader;
+use crate::webhook::Webhook;
 
+mod cache;
+mod cli;
 mod commands;
+mod config;
+mod cooldown;
+mod dedup;
+mod download;
+mod label;
+mod locales;
+mod moderation;
+mod preferences;
+mod public_posts;
+mod registration;
+mod upload;
+mod webhook;
 
-struct Handler;
+/// The member whose avatar is rung
+struct RingTarget<'a> {
+    user: &'a User,
+    /// Roles deciding the tier
+    roles: &'a [RoleId],
+    /// Hash of the Discord avatar of the member when it is the image rung, which makes the result cacheable
+    avatar_hash: Option<&'a str>,
+}
+
+struct Handler {
+    config: Arc<Config>,
+    cooldown: Cooldown,
+    /// Commands accepted per server, whoever runs them
+    guild_rate_limit: GuildRateLimit,
+    recent_interactions: RecentInteractions,
+    preferences: PreferenceStore,
+    /// Gateway sessions resumed or reconnected since startup
+    reconnections: AtomicU64,
+    /// When the bot first connected, for the uptime of /ring-status
+    started_at: OnceLock<Instant>,
+    /// Rings that could not be loaded when the bot last connected
+    ring_failures: Mutex<Vec<String>>,
+    /// Ringed avatars saved on disk, disabled when unset
+    cache: Option<Arc<AvatarCache>>,
+    /// Results kept for their "Post publicly" button
+    public_posts: PublicPosts,
+    /// Bucket receiving a copy of the ringed avatars, disabled when unset
+    uploader: Option<Uploader>,
+    /// Translations of the command names and descriptions
+    locales: Locales,
+    /// Check of the images before they are rung
+    classifier: Arc<dyn Classifier>,
+}
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn ready(&self, ctx: Context, ready: Ready) {
-        println!("{} is connected!", ready.user.name);
+        info!("{} is connected!", ready.user.name);
+        self.started_at.get_or_init(Instant::now);
+        let ring_failures = commands::ring_status::check_rings(&self.config);
+        for failure in &ring_failures {
+            error!("Cannot load the ring of {}", failure);
+        }
+        *self.ring_failures.lock().unwrap() = ring_failures;
 
-        let command = Command::create_global_application_command(
-            &ctx.http,
-            |command| { commands::ring::register(command) },
-        ).await;
+        registration::register(&ctx.http, self.config.dev_guild, &self.locales).await;
+        if self.config.registration_checks > 0 {
+            tokio::spawn(registration::verify(ctx.http.clone(), self.config.dev_guild, self.config.registration_checks));
+        }
+
+        if self.config.self_test {
+            commands::ring::self_test(&self.config);
+        }
+    }
+
+    async fn resume(&self, _ctx: Context, _resumed: ResumedEvent) {
+        let reconnections = self.reconnections.fetch_add(1, Ordering::Relaxed) + 1;
+        info!("Resumed the gateway session ({} reconnections since startup)", reconnections);
+    }
 
-        println!("Registered command: {:#?}", command);
+    async fn shard_stage_update(&self, _ctx: Context, update: ShardStageUpdateEvent) {
+        info!("Shard {} went from {} to {}", update.shard_id, update.old, update.new);
     }
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::MessageComponent(component) = &interaction {
+            self.post_publicly(&ctx, component).await;
+            return;
+        }
         if let Interaction::ApplicationCommand(command) = interaction {
-            Self::respond_ack(&ctx, &command).await;
+            if !self.recent_interactions.first_seen(command.id) {
+                warn!("Ignoring duplicate interaction {}", command.id);
+                return;
+            }
+
+            // the interaction id is unique per request, it ties together the logs of one request
+            info!("[{}] Received /{} from user {}", command.id, command.data.name, command.user.id);
+            if !self.config.is_guild_allowed(command.guild_id) {
+                warn!("[{}] Rejected: server {:?} is not allowed", command.id, command.guild_id);
+                Self::respond_ack(&ctx, &command, "This app is not enabled on this server.").await;
+                return;
+            }
+            if command.guild_id.is_some_and(|guild_id| !self.guild_rate_limit.check(guild_id)) {
+                warn!("[{}] Rejected: server {:?} is over its rate limit", command.id, command.guild_id);
+                Self::respond_ack(&ctx, &command, "This server is busy, please try again shortly.").await;
+                return;
+            }
+            Self::respond_ack(&ctx, &command, &self.config.ack_message).await;
+
+            match command.data.name.as_str() {
+                "unring" => self.unring(&ctx, &command).await,
+                "ring-prefs" => self.ring_prefs(&ctx, &command).await,
+                "ring-inspect" => self.ring_inspect(&ctx, &command).await,
+                "ring-status" => self.ring_status(&ctx, &command).await,
+                "ring-pregenerate" => self.ring_pregenerate(&ctx, &command).await,
+                "ring-team" => self.ring_team(&ctx, &command).await,
+                commands::ring::USER_COMMAND_NAME => self.ring_user(&ctx, &command).await,
+                // the message context-menu command goes through /ring with the image of the message
+                "ring" | commands::ring::MESSAGE_COMMAND_NAME => self.ring(&ctx, &command).await,
+                name => {
+                    // a command registered by a newer or older version of the bot
+                    warn!("[{}] Unknown command {}", command.id, name);
+                    Self::respond_with_message(&ctx, &command, "This command is not supported anymore.").await;
+                }
+            }
+        }
+    }
+}
+
+fn option_value<'a>(command: &'a ApplicationCommandInteraction, name: &str) -> Option<&'a CommandDataOptionValue> {
+    command.data.options.iter()
+        .find(|data_option| data_option.name == name)
+        .and_then(|data_option| data_option.resolved.as_ref())
+}
+
+fn attachment_option<'a>(command: &'a ApplicationCommandInteraction, name: &str) -> Option<&'a Attachment> {
+    option_value(command, name)
+        .and_then(|option_value|
+            if let CommandDataOptionValue::Attachment(attachment) = option_value {
+                Some(attachment)
+            } else {
+                None
+            })
+}
+
+fn string_option<'a>(command: &'a ApplicationCommandInteraction, name: &str) -> Option<&'a String> {
+    option_value(command, name)
+        .and_then(|option_value|
+            if let CommandDataOptionValue::String(value) = option_value {
+                Some(value)
+            } else {
+                None
+            })
+}
+
+fn boolean_option(command: &ApplicationCommandInteraction, name: &str) -> Option<bool> {
+    option_value(command, name)
+        .and_then(|option_value|
+            if let CommandDataOptionValue::Boolean(value) = option_value {
+                Some(*value)
+            } else {
+                None
+            })
+}
+
+fn integer_option(command: &ApplicationCommandInteraction, name: &str) -> Option<i64> {
+    option_value(command, name)
+        .and_then(|option_value|
+            if let CommandDataOptionValue::Integer(value) = option_value {
+                Some(*value)
+            } else {
+                None
+            })
+}
+
+/// The first image attached to the message targeted by a context-menu command,
+/// or to the message it replies to when it has none
+fn target_message_image(command: &ApplicationCommandInteraction) -> Option<&Attachment> {
+    let message_id = command.data.target_id?.to_message_id();
+    message_image(command.data.resolved.messages.get(&message_id)?)
+}
+
+/// The first image attached to the message, or to the message it replies to when it has none
+fn message_image(message: &Message) -> Option<&Attachment> {
+    first_image(message).or_else(|| message.referenced_message.as_deref().and_then(first_image))
+}
+
+fn first_image(message: &Message) -> Option<&Attachment> {
+    message.attachments.iter()
+        .find(|attachment| attachment.content_type.as_ref().is_some_and(|content_type| content_type.starts_with("image/")))
+}
+
+/// The message of a failed request: the reason and hint of user recoverable errors, the reference to quote in any case
+fn failure_message(err: &anyhow::Error, reference: impl Display) -> String {
+    let (message, hint) = match err.downcast_ref::<UserRecoverableError>() {
+        Some(user_recoverable_error) => (format!("{}", &user_recoverable_error), user_recoverable_error.hint()),
+        None => (String::from("Unexpected error"), None),
+    };
+    let message = format!("{} (Error ref: {})", message, reference);
+    match hint {
+        Some(hint) => format!("{}\n{}", message, hint),
+        None => message,
+    }
+}
+
+/// Body of an edit of the original response showing the content and the `files` attached to the edit only
+fn edit_payload(content: &str, files: usize) -> Value {
+    // the attachments left out of the list are removed, those of a preview in particular
+    let attachments = (0..files).map(|id| json!({"id": id})).collect::<Vec<Value>>();
+    json!({"content": content, "attachments": attachments})
+}
+
+/// Whole days elapsed since the member joined the server, None when unknown
+fn days_since(joined_at: Option<Timestamp>, now: Timestamp) -> Option<u64> {
+    let seconds = now.unix_timestamp() - joined_at?.unix_timestamp();
+    Some(seconds.max(0) as u64 / 86400)
+}
+
+/// Discord error codes of an interaction that expired or was answered already, as happens with duplicate deliveries
+const UNKNOWN_INTERACTION: isize = 10062;
+const INTERACTION_ALREADY_ACKNOWLEDGED: isize = 40060;
+
+fn log_response_error(command: &ApplicationCommandInteraction, action: &str, why: &SerenityError) {
+    let stale = match why {
+        SerenityError::Http(http_error) => matches!(
+            http_error.as_ref(),
+            HttpError::UnsuccessfulRequest(response)
+                if response.error.code == UNKNOWN_INTERACTION || response.error.code == INTERACTION_ALREADY_ACKNOWLEDGED
+        ),
+        _ => false,
+    };
+    if stale {
+        warn!("[{}] Cannot {}: the interaction expired or was already answered", command.id, action);
+    } else {
+        error!("[{}] Cannot {}: {}", command.id, action, why);
+    }
+}
+
+impl Preferences {
+    /// The options set in the command
+    fn from_command(command: &ApplicationCommandInteraction) -> anyhow::Result<Self> {
+        let ring_emoji = string_option(command, "emoji")
+            .map(|emoji| commands::ring_prefs::parse_custom_emoji(emoji))
+            .transpose()?;
+        Ok(Preferences {
+            ring_behind: boolean_option(command, "ring_behind"),
+            dpi: integer_option(command, "dpi").map(|dpi| dpi as u32),
+            label: boolean_option(command, "label"),
+            shadow: boolean_option(command, "shadow"),
+            flip_h: boolean_option(command, "flip_h"),
+            flip_v: boolean_option(command, "flip_v"),
+            ring_opacity: integer_option(command, "ring_opacity").map(|opacity| opacity.clamp(0, 100) as u8),
+            keep_ring_size: boolean_option(command, "keep_ring_size"),
+            join_badge: boolean_option(command, "join_badge"),
+            ring_emoji,
+        })
+    }
+}
+
+impl Handler {
+    async fn ring(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        let user_image = attachment_option(command, "avatar").or_else(|| target_message_image(command));
+        let user_image_url = string_option(command, "url");
+
+        let member = command.member.as_ref();
+
+        if member.is_none() {
+            self.respond_with_error(ctx, command, "No user info found.").await;
+            return;
+        }
 
-            let user_image = command.data.options.get(0)
-                .and_then(|data_option| data_option.resolved.as_ref())
-                .and_then(|option_value|
-                    if let CommandDataOptionValue::Attachment(avatar) = option_value {
-                        Some(avatar)
-                    } else {
-                        None
-                    });
+        if !self.check_cooldown(ctx, command, member.unwrap()).await {
+            return;
+        }
+
+        if let Some(attachment) = user_image {
+            if let Err(err) = self.config.check_content_type(attachment.content_type.as_deref()) {
+                self.respond_with_failure(ctx, command, &err.into()).await;
+                return;
+            }
+        }
+
+        // without an image the member's Discord avatar is rung, which the cache can serve, while an uploaded
+        // image is never served from it
+        let member = member.unwrap();
+        let own_avatar_url = member.user.face();
+        let image_url = ImageUrl(user_image_url.map(String::as_str).unwrap_or(&own_avatar_url));
+        let avatar: &dyn AvatarSource = match user_image {
+            Some(attachment) => attachment,
+            None => &image_url,
+        };
+        let avatar_hash = match (user_image, user_image_url) {
+            (None, None) => member.user.avatar.as_deref(),
+            _ => None,
+        };
+
+        let options = match self.ring_options(ctx, command, member).await {
+            Ok(options) => options,
+            Err(err) => {
+                self.respond_with_failure(ctx, command, &err).await;
+                return;
+            }
+        };
+
+        let target = RingTarget { user: &member.user, roles: &member.roles, avatar_hash };
+        self.ring_and_respond(ctx, command, target, avatar, &options).await;
+    }
+
+    /// Ring the current avatar of the member targeted by the user context-menu command
+    async fn ring_user(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        let member = match command.member.as_ref() {
+            Some(member) => member,
+            None => {
+                self.respond_with_error(ctx, command, "No user info found.").await;
+                return;
+            }
+        };
+
+        let target = command.data.target_id
+            .map(|target_id| target_id.to_user_id())
+            .and_then(|user_id| Some((command.data.resolved.users.get(&user_id)?, command.data.resolved.members.get(&user_id)?)));
+        let (target_user, target_member) = match target {
+            Some(target) => target,
+            None => {
+                self.respond_with_error(ctx, command, "This user is not a member of the server.").await;
+                return;
+            }
+        };
+        if target_user.id != member.user.id && !self.config.ring_others {
+            self.respond_with_error(ctx, command, "You can only ring your own avatar.").await;
+            return;
+        }
 
-            let member = command.member.as_ref();
+        if !self.check_cooldown(ctx, command, member).await {
+            return;
+        }
 
-            if member.is_none() {
-                Self::respond_with_error(&ctx, &command, "No user info found.").await;
+        let mut options = match self.ring_options(ctx, command, member).await {
+            Ok(options) => options,
+            Err(err) => {
+                self.respond_with_failure(ctx, command, &err).await;
                 return;
             }
+        };
+        // the badge counts the days of the member whose avatar is rung
+        if options.join_days.is_some() {
+            options.join_days = days_since(target_member.joined_at, Timestamp::now());
+        }
+
+        let avatar_url = target_user.face();
+        let target = RingTarget { user: target_user, roles: &target_member.roles, avatar_hash: target_user.avatar.as_deref() };
+        self.ring_and_respond(ctx, command, target, &ImageUrl(&avatar_url), &options).await;
+    }
+
+    /// Tell the member to wait and return false if they are still cooling down, the use being recorded once rung
+    async fn check_cooldown(&self, ctx: &Context, command: &ApplicationCommandInteraction, member: &Member) -> bool {
+        if !self.config.bypasses_cooldown(&member.roles) {
+            if let Err(remaining) = self.cooldown.check(member.user.id) {
+                let message = format!("Please wait {} more seconds before using /ring again.", remaining.as_secs() + 1);
+                self.respond_with_error(ctx, command, &message).await;
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The options of the command, the member's saved preferences filling in the options left out
+    async fn ring_options(&self, ctx: &Context, command: &ApplicationCommandInteraction, member: &Member) -> anyhow::Result<RingOptions> {
+        let sizes = string_option(command, "sizes")
+            .map(|sizes| commands::ring::parse_sizes(sizes, self.config.max_dimension))
+            .transpose()?;
+        let tint = string_option(command, "tint")
+            .map(|tint| parse_hex_color(tint)
+                .map_err(|_| UserRecoverableError::new(format!("Invalid tint {}, expected a colour formatted as #rrggbb", tint))))
+            .transpose()?;
+        let blend_mode = string_option(command, "blend")
+            .map(|mode| parse_blend_mode(mode)
+                .map_err(|_| UserRecoverableError::new(format!("Invalid blend mode {}, expected normal, screen or add", mode))))
+            .transpose()?;
+        let style = string_option(command, "style")
+            .map(|style| AvatarStyle::parse(style)
+                .ok_or_else(|| UserRecoverableError::new(format!("Invalid style {}, expected color, grayscale or sepia", style))))
+            .transpose()?
+            .unwrap_or_default();
+        let mut preferences = self.preferences.get(member.user.id);
+        preferences.merge(Preferences::from_command(command)?);
+        let no_ring = boolean_option(command, "no_ring").unwrap_or(false);
+        let emoji_ring = match preferences.ring_emoji {
+            // the emoji would not be drawn anyway
+            Some(_) if no_ring => None,
+            Some(emoji) => {
+                // a saved emoji may come from another server or have been deleted since
+                commands::ring_prefs::check_guild_emoji(&ctx.http, command.guild_id, emoji).await?;
+                let url = commands::ring_prefs::emoji_url(emoji);
+                Some(commands::ring_prefs::download_emoji(&ImageUrl(&url)).await?)
+            }
+            None => None,
+        };
+        Ok(RingOptions {
+            sizes: sizes.unwrap_or_default(),
+            ring_behind: preferences.ring_behind.unwrap_or(false),
+            dpi: preferences.dpi,
+            label: preferences.label.unwrap_or(false),
+            shadow: preferences.shadow.unwrap_or(false),
+            no_ring,
+            flip_h: preferences.flip_h.unwrap_or(false),
+            flip_v: preferences.flip_v.unwrap_or(false),
+            event: boolean_option(command, "event").unwrap_or(false),
+            ring_opacity: preferences.ring_opacity,
+            keep_ring_size: preferences.keep_ring_size.unwrap_or(false),
+            progress: integer_option(command, "progress").map(|progress| progress.clamp(0, 100) as u8),
+            tint,
+            crop: boolean_option(command, "crop").unwrap_or(false),
+            sharpen: boolean_option(command, "sharpen").unwrap_or(false),
+            as_data_uri: boolean_option(command, "as_data_uri").unwrap_or(false),
+            ico: boolean_option(command, "ico").unwrap_or(false),
+            blend_mode,
+            animate_reveal: boolean_option(command, "animate_reveal").unwrap_or(false),
+            sticker: boolean_option(command, "sticker").unwrap_or(false),
+            style,
+            variant: string_option(command, "variant").cloned(),
+            join_days: if preferences.join_badge.unwrap_or(false) { days_since(member.joined_at, Timestamp::now()) } else { None },
+            emoji_ring,
+        })
+    }
 
-            if user_image.is_none() {
-                Self::respond_with_error(&ctx, &command, "No user image (attachment) found.").await;
+    /// Ring the avatar with the tier of the roles of the target and send back the result.
+    /// The Discord avatar of the target is served from the cache when rung with the default options.
+    async fn ring_and_respond(&self, ctx: &Context, command: &ApplicationCommandInteraction, target: RingTarget<'_>, avatar: &dyn AvatarSource, options: &RingOptions) {
+        let RingTarget { user, roles, avatar_hash } = target;
+        // a random tier would be picked again when ringing, so it cannot name the cached file, and it is not
+        // picked here at all so that a single tier is logged
+        let cache_key = match (&self.cache, avatar_hash) {
+            (Some(cache), Some(avatar_hash)) if *options == RingOptions::default() && !self.config.random_tier => {
+                commands::ring::resolve_tier(&self.config, roles).ok().map(|tier| (cache, avatar_hash, &tier.name))
+            }
+            _ => None,
+        };
+        if let Some((cache, avatar_hash, tier)) = cache_key {
+            if let Some((data, filename)) = cache.get(user.id, avatar_hash, tier) {
+                info!("[{}] Served the cached {} ring for user {}", command.id, tier, user.id);
+                self.cooldown.record(command.user.id);
+                let content = format!("Here is your {} avatar!", tier);
+                let filename = commands::ring::user_filename(&user.name, user.id, &filename);
+                self.respond_with_result(ctx, command, &content, vec![(data, filename)], false).await;
                 return;
             }
+        }
+
+        let moderated = Moderated { source: avatar, classifier: self.classifier.as_ref() };
+        let (result, previewed) = if self.config.preview && !options.no_ring {
+            self.ring_with_preview(ctx, command, roles, &moderated, options).await
+        } else {
+            (commands::ring::run(&self.config, roles, &moderated, options).await, false)
+        };
 
-            let response = commands::ring::run(member.unwrap(), user_image.unwrap()).await;
-            match response {
-                Ok(avatar) => {
-                    Self::respond_with_attachment(&ctx, &command, avatar).await;
+        match result {
+            Ok(avatar) => {
+                if let (Some((cache, avatar_hash, tier)), Some(AttachmentType::Bytes { data, filename })) = (cache_key, avatar.attachments.first()) {
+                    let extension = filename.rsplit('.').next().unwrap_or("png");
+                    if let Err(err) = cache.put(user.id, avatar_hash, tier, data, extension) {
+                        error!("[{}] Failed to cache the avatar: {:#}", command.id, err);
+                    }
                 }
-                Err(err) => {
-                    println!("Failed to create an avatar: {}", err);
-                    match err.downcast_ref::<UserRecoverableError>() {
-                        Some(user_recoverable_error) => {
-                            Self::respond_with_error(&ctx, &command, &format!("{}", &user_recoverable_error)).await;
-                        }
-                        None => {
-                            Self::respond_with_error(&ctx, &command, "Unexpected error").await;
-                        }
+                self.cooldown.record(command.user.id);
+                self.respond_with_ringed(ctx, command, user, avatar, options, previewed).await;
+            }
+            Err(err) => {
+                error!("[{}] Failed to create an avatar: {}", command.id, err);
+                // the preview of an avatar that cannot be finished would be misleading
+                if previewed {
+                    let payload = edit_payload("The full avatar could not be prepared.", 0);
+                    if let Err(why) = Self::edit_original_response(ctx, command, payload, Vec::new()).await {
+                        log_response_error(command, "remove the preview", &why);
                     }
                 }
+                self.respond_with_failure(ctx, command, &err).await;
             }
         }
     }
-}
 
-impl Handler {
-    async fn respond_ack(ctx: &Context, command: &ApplicationCommandInteraction) {
+    /// Ring the avatar after showing a preview of it, returning the result along with whether the preview was shown
+    async fn ring_with_preview<'a>(&self, ctx: &Context, command: &ApplicationCommandInteraction, roles: &[RoleId], avatar: &dyn AvatarSource, options: &RingOptions) -> (anyhow::Result<RingedAvatar<'a>>, bool) {
+        let pending = commands::ring::download(&self.config, avatar).await
+            .and_then(|avatar| PendingRing::new(&self.config, roles, &avatar, options));
+        match pending {
+            Ok(pending) => {
+                let preview = self.send_preview(ctx, command, &pending, options).await;
+                (pending.ring(&self.config, options), preview)
+            }
+            Err(err) => (Err(err), false),
+        }
+    }
+
+    /// Show a quick low-resolution version of the ringed avatar in the acknowledgement, returning whether it was.
+    /// Failures are only logged, the full avatar following anyway.
+    async fn send_preview(&self, ctx: &Context, command: &ApplicationCommandInteraction, pending: &PendingRing<'_>, options: &RingOptions) -> bool {
+        let preview = match pending.preview(&self.config, options) {
+            Ok(preview) => preview,
+            Err(err) => {
+                error!("[{}] Failed to create a preview: {:#}", command.id, err);
+                return false;
+            }
+        };
+        let payload = edit_payload("Preview, the full avatar is on its way...", 1);
+        match Self::edit_original_response(ctx, command, payload, vec![preview]).await {
+            Ok(_) => true,
+            Err(why) => {
+                log_response_error(command, "send a preview", &why);
+                false
+            }
+        }
+    }
+
+    /// Replace the acknowledgement of the command, attachments included, with the payload and the files
+    async fn edit_original_response(ctx: &Context, command: &ApplicationCommandInteraction, payload: Value, files: Vec<AttachmentType<'_>>) -> serenity::Result<Message> {
+        // the edit endpoints of serenity keep the attachments already sent, this one replaces them
+        let mut request = RequestBuilder::new(RouteInfo::EditOriginalInteractionResponse {
+            application_id: command.application_id.0,
+            interaction_token: &command.token,
+        });
+        request.multipart(Some(Multipart { files, payload_json: Some(payload), fields: vec![] }));
+        ctx.http.fire::<Message>(request.build()).await
+    }
+
+    /// Send back the ringed avatar of `user` with its warning and upload link, as files or as a data URI,
+    /// in place of the preview if one was shown
+    async fn respond_with_ringed(&self, ctx: &Context, command: &ApplicationCommandInteraction, user: &User, avatar: RingedAvatar<'_>, options: &RingOptions, previewed: bool) {
+        let mut content = match &avatar.tier {
+            Some(tier) => {
+                info!("[{}] Applied the {} ring for user {}", command.id, tier, user.id);
+                format!("Here is your {} avatar!", tier)
+            }
+            None => {
+                info!("[{}] Cropped the avatar of user {}", command.id, user.id);
+                String::from("Here is your cropped avatar!")
+            }
+        };
+        if let Some(warning) = &avatar.warning {
+            content = format!("{}\n{}", content, warning);
+        }
+        if let (Some(uploader), Some(AttachmentType::Bytes { data, filename })) = (&self.uploader, avatar.attachments.first()) {
+            let key = format!("{}/{}-{}", user.id, command.id, filename);
+            match uploader.upload(&key, data, commands::ring::mime_type(filename)).await {
+                Ok(url) => content = format!("{}\n{}", content, url),
+                Err(err) => error!("[{}] Failed to upload the avatar: {:#}", command.id, err),
+            }
+        }
+        if !options.as_data_uri {
+            let files = avatar.attachments.into_iter()
+                .filter_map(|attachment| match attachment {
+                    AttachmentType::Bytes { data, filename } => {
+                        Some((data.into_owned(), commands::ring::user_filename(&user.name, user.id, &filename)))
+                    }
+                    _ => None,
+                })
+                .collect();
+            self.respond_with_result(ctx, command, &content, files, previewed).await;
+            return;
+        }
+        match commands::ring::data_uri(&avatar.attachments) {
+            Ok(uri) if previewed => {
+                if let Err(why) = Self::edit_original_response(ctx, command, edit_payload(&uri, 0), Vec::new()).await {
+                    log_response_error(command, "send back a message", &why);
+                }
+            }
+            Ok(uri) => Self::respond_with_message(ctx, command, &uri).await,
+            Err(err) => self.respond_with_failure(ctx, command, &err).await,
+        }
+    }
+
+    /// Ring the two avatars of /ring-team in a single ring
+    async fn ring_team(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        let member = match command.member.as_ref() {
+            Some(member) => member,
+            None => {
+                self.respond_with_error(ctx, command, "No user info found.").await;
+                return;
+            }
+        };
+        if !self.check_cooldown(ctx, command, member).await {
+            return;
+        }
+
+        let (first, second) = match (attachment_option(command, "avatar"), attachment_option(command, "partner")) {
+            (Some(first), Some(second)) => (first, second),
+            _ => {
+                self.respond_with_error(ctx, command, "Two user images (attachments) are needed.").await;
+                return;
+            }
+        };
+        for attachment in [first, second] {
+            if let Err(err) = self.config.check_content_type(attachment.content_type.as_deref()) {
+                self.respond_with_failure(ctx, command, &err.into()).await;
+                return;
+            }
+        }
+        let split = string_option(command, "split")
+            .and_then(|split| Split::parse(split))
+            .unwrap_or_default();
+        let options = match self.ring_options(ctx, command, member).await {
+            Ok(options) => options,
+            Err(err) => {
+                self.respond_with_failure(ctx, command, &err).await;
+                return;
+            }
+        };
+
+        let first = Moderated { source: first, classifier: self.classifier.as_ref() };
+        let second = Moderated { source: second, classifier: self.classifier.as_ref() };
+        match commands::ring_team::run(&self.config, &member.roles, &first, &second, split, &options).await {
+            Ok(avatar) => {
+                self.cooldown.record(command.user.id);
+                self.respond_with_ringed(ctx, command, &member.user, avatar, &options, false).await;
+            }
+            Err(err) => {
+                error!("[{}] Failed to create a team avatar: {}", command.id, err);
+                self.respond_with_failure(ctx, command, &err).await;
+            }
+        }
+    }
+
+    async fn unring(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        let ringed_image = match attachment_option(command, "avatar") {
+            Some(attachment) => attachment,
+            None => {
+                self.respond_with_error(ctx, command, "No ringed image (attachment) found.").await;
+                return;
+            }
+        };
+        if let Err(err) = self.config.check_content_type(ringed_image.content_type.as_deref()) {
+            self.respond_with_failure(ctx, command, &err.into()).await;
+            return;
+        }
+
+        let moderated = Moderated { source: ringed_image, classifier: self.classifier.as_ref() };
+        match commands::unring::run(&self.config, &moderated).await {
+            Ok(avatar) => {
+                Self::respond_with_attachments(ctx, command, "Here is your avatar without the ring!", vec![avatar]).await;
+            }
+            Err(err) => {
+                error!("[{}] Failed to remove a ring: {}", command.id, err);
+                self.respond_with_failure(ctx, command, &err).await;
+            }
+        }
+    }
+
+    async fn ring_prefs(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        let result = if boolean_option(command, "reset").unwrap_or(false) {
+            self.preferences.reset(command.user.id)
+        } else {
+            self.save_preferences(ctx, command).await
+        };
+
+        match result {
+            Ok(()) => Self::respond_with_message(ctx, command, "Your /ring preferences are saved.").await,
+            Err(err) => {
+                error!("[{}] Failed to save the preferences: {}", command.id, err);
+                self.respond_with_failure(ctx, command, &err).await;
+            }
+        }
+    }
+
+    /// Save the options of the command, once its emoji is known to be one of the server
+    async fn save_preferences(&self, ctx: &Context, command: &ApplicationCommandInteraction) -> anyhow::Result<()> {
+        let preferences = Preferences::from_command(command)?;
+        if let Some(emoji) = preferences.ring_emoji {
+            commands::ring_prefs::check_guild_emoji(&ctx.http, command.guild_id, emoji).await?;
+        }
+        self.preferences.update(command.user.id, preferences)
+    }
+
+    async fn ring_inspect(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        let ring = match attachment_option(command, "ring") {
+            Some(attachment) => attachment,
+            None => {
+                self.respond_with_error(ctx, command, "No ring image (attachment) found.").await;
+                return;
+            }
+        };
+
+        match commands::ring_inspect::run(&self.config, ring).await {
+            Ok(report) => Self::respond_with_message(ctx, command, &report).await,
+            Err(err) => {
+                error!("[{}] Failed to inspect a ring: {}", command.id, err);
+                self.respond_with_failure(ctx, command, &err).await;
+            }
+        }
+    }
+
+    async fn ring_pregenerate(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        let guild_id = match command.guild_id {
+            Some(guild_id) => guild_id,
+            None => {
+                self.respond_with_error(ctx, command, "This command can only be used in a server.").await;
+                return;
+            }
+        };
+
+        let cache = match &self.cache {
+            Some(cache) => cache.clone(),
+            None => {
+                self.respond_with_error(ctx, command, "The avatar cache is not enabled on this server").await;
+                return;
+            }
+        };
+
+        // ringing a whole server can outlast the interaction token, the report is sent if it is still valid
+        Self::respond_with_message(ctx, command, "Pregenerating the avatars in the background, the report follows once done.").await;
+        let (config, http, command) = (self.config.clone(), ctx.http.clone(), command.clone());
+        tokio::spawn(async move {
+            let report = match commands::ring_pregenerate::run(config, &http, guild_id, cache).await {
+                Ok(report) => report,
+                Err(err) => {
+                    error!("[{}] Failed to pregenerate the avatars: {:#}", command.id, err);
+                    String::from("Failed to pregenerate the avatars.")
+                }
+            };
+            info!("[{}] {}", command.id, report);
+            if let Err(why) = command.create_followup_message(&http, |response| response.ephemeral(true).content(&report)).await {
+                log_response_error(&command, "send the pregeneration report", &why);
+            }
+        });
+    }
+
+    async fn ring_status(&self, ctx: &Context, command: &ApplicationCommandInteraction) {
+        let uptime = self.started_at.get().map(Instant::elapsed).unwrap_or_default();
+        let status = commands::ring_status::run(&self.config, uptime, self.reconnections.load(Ordering::Relaxed), &self.ring_failures.lock().unwrap());
+        Self::respond_with_message(ctx, command, &status).await;
+    }
+
+    /// Report a failed request, with the interaction id for the user to quote when reporting the problem
+    async fn respond_with_failure(&self, ctx: &Context, command: &ApplicationCommandInteraction, err: &anyhow::Error) {
+        self.respond_with_error(ctx, command, &failure_message(err, command.id)).await;
+    }
+
+    async fn respond_ack(ctx: &Context, command: &ApplicationCommandInteraction, content: &str) {
         if let Err(why) = &command
             .create_interaction_response(
                 &ctx.http,
@@ -85,54 +786,332 @@ impl Handler {
                         .interaction_response_data(
                             |message| {
                                 message.ephemeral(true);
-                                message.content("Preparing your avatar...")
+                                message.content(content)
                             })
                 })
             .await
         {
-            println!("Cannot respond to slash command: {}", why);
+            log_response_error(command, "respond to slash command", why);
         }
     }
 
     #[allow(clippy::needless_lifetimes)]
-    async fn respond_with_attachment<'a, 'b>(ctx: &'a Context, command: &ApplicationCommandInteraction, attachment: AttachmentType<'b>) {
+    async fn respond_with_attachments<'a, 'b>(ctx: &'a Context, command: &ApplicationCommandInteraction, content: &str, attachments: Vec<AttachmentType<'b>>) {
         if let Err(why) = command.create_followup_message(
             &ctx.http,
             |response| {
                 response.ephemeral(true);
-                response.add_file(attachment)
+                response.content(content.to_string());
+                response.add_files(attachments)
             })
             .await
         {
-            println!("Cannot send back an updated avatar: {}", why);
+            log_response_error(command, "send back an updated avatar", &why);
         }
     }
 
-    async fn respond_with_error(ctx: &Context, command: &ApplicationCommandInteraction, err_msg: &str) {
+    /// Send the ringed avatar along with a button posting it publicly to the channel, replacing the preview if shown
+    async fn respond_with_result(&self, ctx: &Context, command: &ApplicationCommandInteraction, content: &str, files: Vec<PostedFile>, previewed: bool) {
+        let attachments = files.iter()
+            .map(|(data, filename)| AttachmentType::Bytes { data: Cow::from(data.clone()), filename: filename.clone() })
+            .collect::<Vec<AttachmentType>>();
+        let custom_id = self.public_posts.keep(command.id, files);
+        if previewed {
+            let mut payload = edit_payload(content, attachments.len());
+            let mut components = CreateComponents::default();
+            components.create_action_row(|row| row.create_button(|button| {
+                button.custom_id(custom_id).label("Post publicly").style(ButtonStyle::Secondary)
+            }));
+            payload["components"] = Value::from(components.0);
+            if let Err(why) = Self::edit_original_response(ctx, command, payload, attachments).await {
+                log_response_error(command, "send back an updated avatar", &why);
+            }
+            return;
+        }
         if let Err(why) = command.create_followup_message(
             &ctx.http,
             |response| {
                 response.ephemeral(true);
-                response.content(err_msg.to_string())
+                response.content(content.to_string());
+                response.components(|components| components.create_action_row(|row| row.create_button(|button| {
+                    button.custom_id(custom_id).label("Post publicly").style(ButtonStyle::Secondary)
+                })));
+                response.add_files(attachments)
             })
             .await
         {
-            println!("Cannot send back an error message: {}", why);
+            log_response_error(command, "send back an updated avatar", &why);
+        }
+    }
+
+    /// Post the result behind the clicked "Post publicly" button to the channel
+    async fn post_publicly(&self, ctx: &Context, component: &MessageComponentInteraction) {
+        let files = self.public_posts.get(&component.data.custom_id);
+        if files.is_some() {
+            info!("[{}] Posting the avatar of user {} publicly", component.id, component.user.id);
+        }
+        let result = component.create_interaction_response(
+            &ctx.http,
+            |response| {
+                response
+                    .kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| match files {
+                        Some(files) => {
+                            let attachments = files.into_iter()
+                                .map(|(data, filename)| AttachmentType::Bytes { data: Cow::from(data), filename });
+                            message.content(format!("{} rang their avatar!", component.user.name)).add_files(attachments)
+                        }
+                        None => message.ephemeral(true).content("This avatar is no longer available, please ring it again."),
+                    })
+            })
+            .await;
+        if let Err(why) = result {
+            error!("[{}] Cannot post the avatar publicly: {}", component.id, why);
+        }
+    }
+
+    async fn respond_with_message(ctx: &Context, command: &ApplicationCommandInteraction, content: &str) {
+        if let Err(why) = command.create_followup_message(
+            &ctx.http,
+            |response| {
+                response.ephemeral(true);
+                response.content(content.to_string())
+            })
+            .await
+        {
+            log_response_error(command, "send back a message", &why);
+        }
+    }
+
+    /// Send the error as an embed when enabled, as plain text otherwise
+    async fn respond_with_error(&self, ctx: &Context, command: &ApplicationCommandInteraction, err_msg: &str) {
+        if let Err(why) = command.create_followup_message(
+            &ctx.http,
+            |response| {
+                response.ephemeral(true);
+                if !self.config.error_embeds {
+                    return response.content(err_msg.to_string());
+                }
+                response.embed(|embed| {
+                    embed.title("Couldn't apply ring")
+                        .description(err_msg)
+                        .colour(Colour::RED);
+                    if let Some(help_url) = &self.config.help_url {
+                        embed.url(help_url);
+                    }
+                    embed
+                })
+            })
+            .await
+        {
+            log_response_error(command, "send back an error message", &why);
         }
     }
 }
 
+/// Print the tracing events, like those of serenity, as JSON lines for log aggregators or as readable text
+fn init_logging() -> anyhow::Result<()> {
+    let format = env::var("CHAOSRING_LOG_FORMAT").ok();
+    logging_subscriber(format.as_deref(), std::io::stdout)?.try_init()?;
+    Ok(())
+}
+
+/// The subscriber writing the events in the format of `CHAOSRING_LOG_FORMAT`, readable text when unset
+fn logging_subscriber<W>(format: Option<&str>, writer: W) -> anyhow::Result<Box<dyn Subscriber + Send + Sync>>
+    where W: for<'w> MakeWriter<'w> + Send + Sync + 'static
+{
+    let subscriber = tracing_subscriber::fmt().with_max_level(LevelFilter::INFO).with_writer(writer);
+    Ok(match format {
+        Some("json") => Box::new(subscriber.json().finish()),
+        Some("pretty") | None => Box::new(subscriber.pretty().finish()),
+        Some(format) => anyhow::bail!("Invalid CHAOSRING_LOG_FORMAT value: {}, expected json or pretty", format),
+    })
+}
+
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
+    // before loading the config, whose warnings are logged
+    if let Err(err) = init_logging() {
+        eprintln!("{:#}", err);
+        std::process::exit(1);
+    }
+
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if args.first().is_some_and(|command| command == "process") {
+        let result = Config::load().and_then(|config| cli::process(&config, &args[1..]));
+        if let Err(err) = result {
+            eprintln!("{:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let token = env::var("DISCORD_TOKEN").expect("Expected a discord token in the environment");
 
+    let cooldown = env::var("CHAOSRING_COOLDOWN_SECS")
+        .map(|secs| secs.parse::<u64>().expect("Expected CHAOSRING_COOLDOWN_SECS to be a number of seconds"))
+        .unwrap_or(0);
+    let guild_rate = env::var("CHAOSRING_GUILD_RATE_PER_MIN")
+        .map(|rate| rate.parse::<u32>().expect("Expected CHAOSRING_GUILD_RATE_PER_MIN to be a number of commands"))
+        .unwrap_or(0);
+    let guild_burst = env::var("CHAOSRING_GUILD_BURST")
+        .map(|burst| burst.parse::<u32>().expect("Expected CHAOSRING_GUILD_BURST to be a number of commands"))
+        .unwrap_or(guild_rate);
+    let config = Arc::new(Config::load().expect("Invalid ring configuration"));
+    info!("Loaded ring tiers: {:?}", config.tiers.iter().map(|tier| &tier.name).collect::<Vec<&String>>());
+    let dedup_window = env::var("CHAOSRING_DEDUP_WINDOW_SECS")
+        .map(|secs| secs.parse::<u64>().expect("Expected CHAOSRING_DEDUP_WINDOW_SECS to be a number of seconds"))
+        .unwrap_or(60);
+    let preferences = match env::var("CHAOSRING_PREFS_FILE") {
+        Ok(path) => PreferenceStore::load(path.into()),
+        Err(_) => PreferenceStore::disabled(),
+    };
+    let cache = env::var("CHAOSRING_CACHE_DIR").ok()
+        .map(|dir| Arc::new(AvatarCache::new(dir.into()).expect("Invalid cache directory")));
+    let classifier = moderation::from_env().expect("Invalid classifier configuration");
+    if let Some(webhook) = Webhook::from_env().expect("Invalid webhook configuration") {
+        tokio::spawn(webhook.serve(config.clone(), classifier.clone()));
+    }
+    let handler = Handler {
+        config,
+        cooldown: Cooldown::new(Duration::from_secs(cooldown)),
+        guild_rate_limit: GuildRateLimit::new(guild_rate, guild_burst),
+        recent_interactions: RecentInteractions::new(Duration::from_secs(dedup_window)),
+        preferences,
+        reconnections: AtomicU64::new(0),
+        started_at: OnceLock::new(),
+        ring_failures: Mutex::new(Vec::new()),
+        cache,
+        public_posts: PublicPosts::new(),
+        uploader: Uploader::from_env().expect("Invalid S3 configuration"),
+        locales: Locales::from_env().expect("Invalid locale file"),
+        classifier,
+    };
+
     let mut client = Client::builder(token, GatewayIntents::empty())
-        .event_handler(Handler)
+        .event_handler(handler)
         .await
         .expect("Error creating client");
 
     if let Err(why) = client.start().await {
-        println!("Client error: {:?}", why);
+        error!("Client error: {:?}", why);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: u64, attachments: Value, referenced_message: Value) -> Message {
+        serde_json::from_value(json!({
+            "id": id.to_string(),
+            "channel_id": "1",
+            "author": {"id": "2", "username": "fren", "discriminator": "0001", "avatar": null},
+            "content": "",
+            "timestamp": "2024-01-01T00:00:00.000000+00:00",
+            "edited_timestamp": null,
+            "tts": false,
+            "mention_everyone": false,
+            "mentions": [],
+            "mention_roles": [],
+            "attachments": attachments,
+            "embeds": [],
+            "pinned": false,
+            "type": 0,
+            "referenced_message": referenced_message,
+        })).unwrap()
+    }
+
+    fn attachment(id: u64, content_type: &str) -> Value {
+        json!({
+            "id": id.to_string(),
+            "filename": "file",
+            "size": 1024,
+            "url": "https://cdn.discordapp.com/file",
+            "proxy_url": "https://media.discordapp.net/file",
+            "content_type": content_type,
+        })
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn takes_the_image_of_the_replied_message_when_the_message_has_none() {
+        let original = serde_json::to_value(message(10, json!([attachment(100, "image/png")]), Value::Null)).unwrap();
+        let reply = message(11, json!([attachment(101, "text/plain")]), original);
+        assert_eq!(message_image(&reply).map(|image| image.id.0), Some(100));
+
+        let reply_with_image = message(12, json!([attachment(102, "image/jpeg")]), Value::Null);
+        assert_eq!(message_image(&reply_with_image).map(|image| image.id.0), Some(102));
+        assert!(message_image(&message(13, json!([]), Value::Null)).is_none());
+    }
+
+    #[test]
+    fn counts_the_whole_days_since_joining() {
+        let joined_at = Timestamp::parse("2024-01-01T00:00:00Z").unwrap();
+        let now = Timestamp::parse("2024-03-01T12:00:00Z").unwrap();
+        assert_eq!(days_since(Some(joined_at), now), Some(60));
+        assert_eq!(days_since(Some(now), joined_at), Some(0));
+        assert_eq!(days_since(None, now), None);
+    }
+
+    #[test]
+    fn the_role_help_is_appended_to_the_error_of_roleless_members() {
+        let mut config: Config = toml::from_str("tiers = []").unwrap();
+        config.tiers.push(toml::from_str("name = \"DAOist\"\nrole_ids = [1]\nring = \"ring.png\"").unwrap());
+        let err = commands::ring::find_dao_role(&config.tiers, &[RoleId(2)], None).unwrap_err();
+        assert_eq!(failure_message(&err, 42), "Error while preparing an avatar: User does not hold any of the ring roles (DAOist) (Error ref: 42)");
+
+        let err = commands::ring::find_dao_role(&config.tiers, &[RoleId(2)], Some("Get verified in #welcome")).unwrap_err();
+        assert_eq!(failure_message(&err, 42), "Error while preparing an avatar: User does not hold any of the ring roles (DAOist) (Error ref: 42)\nGet verified in #welcome");
+
+        assert_eq!(failure_message(&anyhow::anyhow!("disk full"), 42), "Unexpected error (Error ref: 42)");
+    }
+
+    #[test]
+    fn json_logs_are_one_object_per_line() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let writer = {
+            let output = output.clone();
+            move || SharedBuffer(output.clone())
+        };
+        let subscriber = logging_subscriber(Some("json"), writer).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            info!(user = 42, "Rung an avatar");
+            warn!("Slow download");
+        });
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().map(|line| serde_json::from_str::<Value>(line).unwrap()).collect::<Vec<Value>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["fields"]["message"], "Rung an avatar");
+        assert_eq!(lines[0]["fields"]["user"], 42);
+        assert_eq!(lines[1]["level"], "WARN");
+
+        assert!(logging_subscriber(Some("xml"), std::io::sink).is_err());
+    }
+
+    /// A log output the test can read back
+    pub(crate) struct SharedBuffer(pub(crate) Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn an_edit_keeps_only_its_own_attachments() {
+        let preview = edit_payload("Preview, the full avatar is on its way...", 1);
+        assert_eq!(preview["attachments"], json!([{"id": 0}]));
+
+        // the preview is attachment 0 of the acknowledgement, replaced by the files of the final edit
+        let full = edit_payload("Here is your DAOist avatar!", 2);
+        assert_eq!(full, json!({"content": "Here is your DAOist avatar!", "attachments": [{"id": 0}, {"id": 1}]}));
+        assert_eq!(edit_payload("The full avatar could not be prepared.", 0)["attachments"], json!([]));
+    }
+}
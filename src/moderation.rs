@@ -0,0 +1,144 @@
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use reqwest::header::CONTENT_TYPE;
+use serde::Deserialize;
+use serenity::async_trait;
+
+use crate::commands::ring::UserRecoverableError;
+use crate::download::AvatarSource;
+
+const CLASSIFIER_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Limit of a whole check, so that a stalled classifier cannot hold the command
+const CLASSIFIER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Check of the uploaded images before they are rung, operators plugging the classifier of their choice
+#[async_trait]
+pub trait Classifier: Send + Sync {
+    /// Whether the image must be refused
+    async fn is_flagged(&self, image: &[u8]) -> anyhow::Result<bool>;
+}
+
+/// Lets every image through, the default
+pub struct NoClassifier;
+
+#[async_trait]
+impl Classifier for NoClassifier {
+    async fn is_flagged(&self, _image: &[u8]) -> anyhow::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Posts the image to an external moderation API answering `{"flagged": true|false}`
+pub struct HttpClassifier {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpClassifier {
+    pub fn new(url: String, timeout: Duration) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(CLASSIFIER_CONNECT_TIMEOUT)
+            .timeout(timeout)
+            .build()?;
+        Ok(HttpClassifier { url, client })
+    }
+}
+
+#[derive(Deserialize)]
+struct Verdict {
+    flagged: bool,
+}
+
+#[async_trait]
+impl Classifier for HttpClassifier {
+    async fn is_flagged(&self, image: &[u8]) -> anyhow::Result<bool> {
+        // a classifier too slow to answer is worth another try, unlike one that is broken
+        let timed_out = |err: reqwest::Error| -> anyhow::Error {
+            if err.is_timeout() {
+                UserRecoverableError::new("Checking the image took too long, please try again").into()
+            } else {
+                err.into()
+            }
+        };
+        let verdict = self.client.post(&self.url)
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(image.to_vec())
+            .send().await.map_err(timed_out)?
+            .error_for_status()?
+            .json::<Verdict>().await
+            .map_err(|err| timed_out(err).context("Invalid answer of the classifier"))?;
+        Ok(verdict.flagged)
+    }
+}
+
+/// The classifier calling `CHAOSRING_CLASSIFIER_URL` if set, otherwise the one letting everything through
+pub fn from_env() -> anyhow::Result<Arc<dyn Classifier>> {
+    Ok(match env::var("CHAOSRING_CLASSIFIER_URL") {
+        Ok(url) => Arc::new(HttpClassifier::new(url, CLASSIFIER_TIMEOUT)?),
+        Err(_) => Arc::new(NoClassifier),
+    })
+}
+
+/// An avatar source refusing the images flagged by the classifier.
+/// Images the classifier fails to check are refused as well.
+pub struct Moderated<'a> {
+    pub source: &'a dyn AvatarSource,
+    pub classifier: &'a dyn Classifier,
+}
+
+#[async_trait]
+impl AvatarSource for Moderated<'_> {
+    async fn download(&self) -> anyhow::Result<Vec<u8>> {
+        let image = self.source.download().await?;
+        if self.classifier.is_flagged(&image).await.context("Cannot classify the image")? {
+            return Err(UserRecoverableError::new("This image cannot be used, please pick another one").into());
+        }
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Refuses every image
+    pub struct FlagEverything;
+
+    #[async_trait]
+    impl Classifier for FlagEverything {
+        async fn is_flagged(&self, _image: &[u8]) -> anyhow::Result<bool> {
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_the_flagged_images() {
+        let image = vec![1, 2, 3];
+        let err = Moderated { source: &image, classifier: &FlagEverything }.download().await.unwrap_err();
+        assert!(err.downcast_ref::<UserRecoverableError>().is_some());
+        assert_eq!(Moderated { source: &image, classifier: &NoClassifier }.download().await.unwrap(), image);
+    }
+
+    #[tokio::test]
+    async fn a_classifier_not_answering_in_time_is_reported_to_the_user() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/classify", listener.local_addr().unwrap());
+        // accepts the connection but never answers
+        let server = tokio::spawn(async move {
+            let _connection = listener.accept().await;
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let classifier = HttpClassifier::new(url, Duration::from_millis(200)).unwrap();
+        let image = vec![1, 2, 3];
+        let err = Moderated { source: &image, classifier: &classifier }.download().await.unwrap_err();
+        let user_recoverable_error = err.downcast_ref::<UserRecoverableError>().unwrap();
+        assert!(user_recoverable_error.to_string().contains("took too long"));
+        server.abort();
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serenity::model::id::UserId;
+use tracing::warn;
+
+use crate::commands::ring::UserRecoverableError;
+
+/// Upper bound of users with saved preferences
+const MAX_USERS: usize = 10_000;
+
+/// Default values of the `/ring` options, used when the user omits them
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Preferences {
+    pub ring_behind: Option<bool>,
+    pub dpi: Option<u32>,
+    pub label: Option<bool>,
+    pub shadow: Option<bool>,
+    pub flip_h: Option<bool>,
+    pub flip_v: Option<bool>,
+    pub ring_opacity: Option<u8>,
+    pub keep_ring_size: Option<bool>,
+    #[serde(default)]
+    pub join_badge: Option<bool>,
+    /// Id of the custom emoji used as the ring
+    #[serde(default)]
+    pub ring_emoji: Option<u64>,
+}
+
+impl Preferences {
+    /// Overwrite the preferences set in `other`
+    pub fn merge(&mut self, other: Preferences) {
+        self.ring_behind = other.ring_behind.or(self.ring_behind);
+        self.dpi = other.dpi.or(self.dpi);
+        self.label = other.label.or(self.label);
+        self.shadow = other.shadow.or(self.shadow);
+        self.flip_h = other.flip_h.or(self.flip_h);
+        self.flip_v = other.flip_v.or(self.flip_v);
+        self.ring_opacity = other.ring_opacity.or(self.ring_opacity);
+        self.keep_ring_size = other.keep_ring_size.or(self.keep_ring_size);
+        self.join_badge = other.join_badge.or(self.join_badge);
+        self.ring_emoji = other.ring_emoji.or(self.ring_emoji);
+    }
+}
+
+/// Per-user preferences saved to a JSON file, disabled without a file
+pub struct PreferenceStore {
+    path: Option<PathBuf>,
+    preferences: Mutex<HashMap<u64, Preferences>>,
+}
+
+impl PreferenceStore {
+    pub fn disabled() -> Self {
+        PreferenceStore { path: None, preferences: Mutex::new(HashMap::new()) }
+    }
+
+    /// Load the preferences saved in the file, which doesn't need to exist yet. A corrupt file is moved aside
+    /// rather than stopping the bot, its preferences being lost until it is fixed by hand.
+    pub fn load(path: PathBuf) -> Self {
+        let preferences = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|err| {
+                let corrupt = with_suffix(&path, ".corrupt");
+                warn!("Invalid preferences file {}, moving it to {} and starting over: {}", path.display(), corrupt.display(), err);
+                if let Err(err) = fs::rename(&path, &corrupt) {
+                    warn!("Cannot move the preferences file {}: {}", path.display(), err);
+                }
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        PreferenceStore { path: Some(path), preferences: Mutex::new(preferences) }
+    }
+
+    pub fn get(&self, user: UserId) -> Preferences {
+        self.preferences.lock().unwrap().get(&user.0).cloned().unwrap_or_default()
+    }
+
+    /// Merge the preferences into the saved ones and write the file
+    pub fn update(&self, user: UserId, update: Preferences) -> anyhow::Result<()> {
+        let path = self.path.as_ref()
+            .ok_or_else(|| UserRecoverableError::new("Preferences are not enabled on this server"))?;
+
+        let mut preferences = self.preferences.lock().unwrap();
+        if !preferences.contains_key(&user.0) && preferences.len() >= MAX_USERS {
+            return Err(UserRecoverableError::new("No more preferences can be saved").into());
+        }
+        preferences.entry(user.0).or_default().merge(update);
+        save(path, &preferences)?;
+        Ok(())
+    }
+
+    pub fn reset(&self, user: UserId) -> anyhow::Result<()> {
+        let path = self.path.as_ref()
+            .ok_or_else(|| UserRecoverableError::new("Preferences are not enabled on this server"))?;
+
+        let mut preferences = self.preferences.lock().unwrap();
+        if preferences.remove(&user.0).is_some() {
+            save(path, &preferences)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the preferences to a temporary file renamed over the file, so that a crash mid-write leaves the
+/// previous file intact
+fn save(path: &Path, preferences: &HashMap<u64, Preferences>) -> anyhow::Result<()> {
+    let temporary = with_suffix(path, ".tmp");
+    fs::write(&temporary, serde_json::to_string(preferences)?)
+        .with_context(|| format!("Cannot write the preferences file {}", temporary.display()))?;
+    fs::rename(&temporary, path)
+        .with_context(|| format!("Cannot replace the preferences file {}", path.display()))?;
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("chaosring-preferences-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn saved_preferences_are_loaded_back() {
+        let path = temp_path("saved");
+        let store = PreferenceStore::load(path.clone());
+        store.update(UserId(1), Preferences { dpi: Some(300), ..Preferences::default() }).unwrap();
+        assert!(!with_suffix(&path, ".tmp").exists());
+
+        assert_eq!(PreferenceStore::load(path).get(UserId(1)).dpi, Some(300));
+    }
+
+    #[test]
+    fn a_corrupt_file_is_moved_aside() {
+        let path = temp_path("corrupt");
+        fs::write(&path, "{\"1\": {\"dpi\": ").unwrap();
+        let store = PreferenceStore::load(path.clone());
+        assert_eq!(store.get(UserId(1)).dpi, None);
+
+        let corrupt = with_suffix(&path, ".corrupt");
+        assert_eq!(fs::read_to_string(&corrupt).unwrap(), "{\"1\": {\"dpi\": ");
+        assert!(!path.exists());
+        // saving starts a fresh file
+        store.update(UserId(2), Preferences { label: Some(true), ..Preferences::default() }).unwrap();
+        assert_eq!(PreferenceStore::load(path).get(UserId(2)).label, Some(true));
+        fs::remove_file(corrupt).unwrap();
+    }
+}
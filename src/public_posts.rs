@@ -0,0 +1,49 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serenity::model::id::InteractionId;
+
+/// Upper bound of results kept for the "Post publicly" button, the oldest are forgotten first
+const MAX_KEPT: usize = 64;
+/// How long the button works, as long as the interaction token of the ephemeral result
+const WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// Prefix of the custom id of the "Post publicly" buttons, followed by the id of the interaction
+pub const BUTTON_PREFIX: &str = "post-publicly:";
+
+/// A file of a result: its bytes and name
+pub type PostedFile = (Vec<u8>, String);
+
+/// Results sent ephemerally, kept for a while so that they can be posted to the channel
+pub struct PublicPosts {
+    results: Mutex<VecDeque<(InteractionId, Instant, Vec<PostedFile>)>>,
+}
+
+impl PublicPosts {
+    pub fn new() -> Self {
+        PublicPosts { results: Mutex::new(VecDeque::new()) }
+    }
+
+    /// Keep the files of the interaction, returning the custom id of the button posting them
+    pub fn keep(&self, id: InteractionId, files: Vec<PostedFile>) -> String {
+        let mut results = self.results.lock().unwrap();
+        let now = Instant::now();
+        while results.front().is_some_and(|(_, kept_at, _)| now.duration_since(*kept_at) >= WINDOW) {
+            results.pop_front();
+        }
+        if results.len() >= MAX_KEPT {
+            results.pop_front();
+        }
+        results.push_back((id, now, files));
+        format!("{}{}", BUTTON_PREFIX, id)
+    }
+
+    /// The files behind the custom id of a button, None once they are forgotten
+    pub fn get(&self, custom_id: &str) -> Option<Vec<PostedFile>> {
+        let id = custom_id.strip_prefix(BUTTON_PREFIX)?.parse::<u64>().ok()?;
+        self.results.lock().unwrap().iter()
+            .find(|(kept_id, kept_at, _)| kept_id.0 == id && kept_at.elapsed() < WINDOW)
+            .map(|(_, _, files)| files.clone())
+    }
+}
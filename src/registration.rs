@@ -0,0 +1,229 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::Value;
+use serenity::builder::CreateApplicationCommands;
+use serenity::http::Http;
+use serenity::model::id::GuildId;
+use serenity::model::prelude::command::{Command, CommandOption};
+use tracing::{error, info, warn};
+
+use crate::commands;
+use crate::locales::Locales;
+
+/// Attempts at registering the commands, waiting twice as long after each failure
+const ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(2);
+/// Wait between two checks that the registered commands are listed
+const VERIFY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What is compared between the registered and the desired commands
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct CommandSummary {
+    name: String,
+    kind: u64,
+    description: String,
+    options: Vec<OptionSummary>,
+}
+
+impl CommandSummary {
+    fn from_command(command: &Command) -> Self {
+        CommandSummary {
+            name: command.name.clone(),
+            kind: command.kind as u64,
+            description: command.description.clone(),
+            options: command.options.iter().map(OptionSummary::from_option).collect(),
+        }
+    }
+
+    /// Summary of a command built by `register_commands`, whose defaults are left out of the JSON
+    fn from_definition(definition: &Value) -> Self {
+        let options = definition["options"].as_array().map(Vec::as_slice).unwrap_or_default();
+        CommandSummary {
+            name: text(definition, "name"),
+            // chat input commands are the default type
+            kind: definition["type"].as_u64().unwrap_or(1),
+            description: text(definition, "description"),
+            options: options.iter().map(OptionSummary::from_definition).collect(),
+        }
+    }
+}
+
+/// What is compared between the registered and the desired options of a command, the numbers being kept as
+/// their JSON text
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct OptionSummary {
+    name: String,
+    description: String,
+    kind: u64,
+    required: bool,
+    /// Name and value of each choice
+    choices: Vec<(String, String)>,
+    min_value: Option<String>,
+    max_value: Option<String>,
+}
+
+impl OptionSummary {
+    fn from_option(option: &CommandOption) -> Self {
+        OptionSummary {
+            name: option.name.clone(),
+            description: option.description.clone(),
+            kind: option.kind as u64,
+            required: option.required,
+            choices: option.choices.iter()
+                .map(|choice| (choice.name.clone(), choice.value.to_string()))
+                .collect(),
+            min_value: option.min_value.as_ref().map(ToString::to_string),
+            max_value: option.max_value.as_ref().map(ToString::to_string),
+        }
+    }
+
+    fn from_definition(definition: &Value) -> Self {
+        let choices = definition["choices"].as_array().map(Vec::as_slice).unwrap_or_default();
+        let number = |key: &str| definition.get(key).filter(|value| value.is_number()).map(Value::to_string);
+        OptionSummary {
+            name: text(definition, "name"),
+            description: text(definition, "description"),
+            kind: definition["type"].as_u64().unwrap_or_default(),
+            required: definition["required"].as_bool().unwrap_or(false),
+            choices: choices.iter()
+                .map(|choice| (text(choice, "name"), choice["value"].to_string()))
+                .collect(),
+            min_value: number("min_value"),
+            max_value: number("max_value"),
+        }
+    }
+}
+
+fn text(value: &Value, key: &str) -> String {
+    value[key].as_str().unwrap_or_default().to_string()
+}
+
+pub fn register_commands<'a>(commands: &'a mut CreateApplicationCommands, locales: &Locales) -> &'a mut CreateApplicationCommands {
+    commands
+        .create_application_command(|command| { locales.localize(commands::ring::register(command)) })
+        .create_application_command(|command| { locales.localize(commands::unring::register(command)) })
+        .create_application_command(|command| { locales.localize(commands::ring_prefs::register(command)) })
+        .create_application_command(|command| { locales.localize(commands::ring_inspect::register(command)) })
+        .create_application_command(|command| { locales.localize(commands::ring_status::register(command)) })
+        .create_application_command(|command| { locales.localize(commands::ring_pregenerate::register(command)) })
+        .create_application_command(|command| { locales.localize(commands::ring_team::register(command)) })
+        .create_application_command(|command| { locales.localize(commands::ring::register_message_command(command)) })
+        .create_application_command(|command| { locales.localize(commands::ring::register_user_command(command)) })
+}
+
+/// JSON definitions of the commands, as sent to Discord
+pub fn definitions(locales: &Locales) -> Vec<Value> {
+    let mut definitions = CreateApplicationCommands::default();
+    register_commands(&mut definitions, locales);
+    definitions.0
+}
+
+/// Register the commands, in the guild if set or globally, unless they are already registered as defined.
+/// Failures are retried with an exponential backoff.
+pub async fn register(http: &Http, guild: Option<GuildId>, locales: &Locales) {
+    let mut delay = FIRST_RETRY_DELAY;
+    for attempt in 1..=ATTEMPTS {
+        match try_register(http, guild, locales).await {
+            Ok(()) => return,
+            Err(err) if attempt < ATTEMPTS => {
+                warn!("Failed to register the commands (attempt {}/{}), retrying in {:?}: {}", attempt, ATTEMPTS, delay, err);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(err) => error!("Failed to register the commands, giving up after {} attempts: {}", ATTEMPTS, err),
+        }
+    }
+}
+
+/// Poll the registered commands until all of them are listed, at most `checks` times, and log the outcome
+pub async fn verify(http: Arc<Http>, guild: Option<GuildId>, checks: u32) {
+    // the translations don't change the names listed
+    let names = definitions(&Locales::default()).iter()
+        .filter_map(|definition| definition["name"].as_str().map(String::from))
+        .collect::<Vec<String>>();
+
+    for check in 1..=checks {
+        let existing = match guild {
+            Some(guild_id) => guild_id.get_application_commands(&http).await,
+            None => Command::get_global_application_commands(&http).await,
+        };
+        match existing {
+            Ok(existing) if names.iter().all(|name| existing.iter().any(|command| &command.name == name)) => {
+                info!("Confirmed that the commands are registered (check {}/{})", check, checks);
+                return;
+            }
+            Ok(_) => warn!("The commands are not all listed yet (check {}/{})", check, checks),
+            Err(err) => error!("Failed to list the registered commands (check {}/{}): {}", check, checks, err),
+        }
+        if check < checks {
+            tokio::time::sleep(VERIFY_INTERVAL).await;
+        }
+    }
+    warn!("Could not confirm that the commands are registered after {} checks", checks);
+}
+
+async fn try_register(http: &Http, guild: Option<GuildId>, locales: &Locales) -> serenity::Result<()> {
+    // the listed commands leave out their translations, which could have changed since they were registered
+    if locales.is_empty() {
+        let mut desired = definitions(locales).iter().map(CommandSummary::from_definition).collect::<Vec<CommandSummary>>();
+        desired.sort();
+
+        let existing = match guild {
+            Some(guild_id) => guild_id.get_application_commands(http).await?,
+            None => Command::get_global_application_commands(http).await?,
+        };
+        let mut registered = existing.iter().map(CommandSummary::from_command).collect::<Vec<CommandSummary>>();
+        registered.sort();
+        if registered == desired {
+            info!("The {} registered commands are up to date, skipping the registration", registered.len());
+            return Ok(());
+        }
+    }
+
+    // guild commands are available at once, global ones can take up to an hour to propagate
+    let commands = match guild {
+        Some(guild_id) => {
+            info!("Registering the commands in the server {} only", guild_id);
+            guild_id.set_application_commands(http, |commands| register_commands(commands, locales)).await?
+        }
+        None => Command::set_global_application_commands(http, |commands| register_commands(commands, locales)).await?,
+    };
+    info!("Registered commands: {:?}", commands.iter().map(|command| &command.name).collect::<Vec<&String>>());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_summaries_of_the_registered_options_match_their_definitions() {
+        let definitions = definitions(&Locales::default());
+        let ring = definitions.iter().find(|definition| definition["name"] == "ring").unwrap();
+        for definition in ring["options"].as_array().unwrap() {
+            // as listed by Discord
+            let option: CommandOption = serde_json::from_value(definition.clone()).unwrap();
+            assert_eq!(OptionSummary::from_option(&option), OptionSummary::from_definition(definition));
+        }
+    }
+
+    #[test]
+    fn changed_choices_and_bounds_are_noticed() {
+        let definition = serde_json::json!({
+            "type": 4, "name": "dpi", "description": "DPI",
+            "min_value": 72, "max_value": 600,
+            "choices": [{"name": "Print", "value": 300}],
+        });
+        let option: CommandOption = serde_json::from_value(definition.clone()).unwrap();
+        let registered = OptionSummary::from_option(&option);
+        assert_eq!(registered, OptionSummary::from_definition(&definition));
+        assert_eq!(registered.choices, vec![(String::from("Print"), String::from("300"))]);
+
+        for (key, value) in [("max_value", serde_json::json!(1200)), ("choices", serde_json::json!([{"name": "Print", "value": 600}]))] {
+            let mut changed = definition.clone();
+            changed[key] = value;
+            assert_ne!(registered, OptionSummary::from_definition(&changed), "{}", key);
+        }
+    }
+}
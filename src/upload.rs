@@ -0,0 +1,50 @@
+use std::env;
+
+use anyhow::Context;
+use s3::{Bucket, Region};
+use s3::creds::Credentials;
+
+use crate::config::load_env_var;
+
+/// Copies of the ringed avatars uploaded to an S3-compatible bucket, for a gallery website
+pub struct Uploader {
+    bucket: Bucket,
+    /// Base of the public links, the key of the avatar being appended to it
+    public_url: String,
+}
+
+impl Uploader {
+    /// The uploader configured by `CHAOSRING_S3_BUCKET` and its related variables, None when unset.
+    /// The credentials come from `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY`.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let name = match env::var("CHAOSRING_S3_BUCKET") {
+            Ok(name) => name,
+            Err(_) => return Ok(None),
+        };
+        let region = load_env_var("CHAOSRING_S3_REGION")?;
+        let endpoint = env::var("CHAOSRING_S3_ENDPOINT").ok();
+        let region = match &endpoint {
+            Some(endpoint) => Region::Custom { region, endpoint: endpoint.clone() },
+            None => region.parse::<Region>()
+                .with_context(|| format!("Invalid CHAOSRING_S3_REGION value: {}", region))?,
+        };
+        let credentials = Credentials::from_env().context("Missing S3 credentials")?;
+        let mut bucket = Bucket::new(&name, region, credentials)?;
+        // S3-compatible services usually expect the bucket in the path rather than in the host name
+        if endpoint.is_some() {
+            bucket = bucket.with_path_style();
+        }
+        let public_url = load_env_var("CHAOSRING_S3_PUBLIC_URL")?;
+
+        Ok(Some(Uploader { bucket, public_url: public_url.trim_end_matches('/').to_string() }))
+    }
+
+    /// Upload the file under `key` and return its public link
+    pub async fn upload(&self, key: &str, data: &[u8], content_type: &str) -> anyhow::Result<String> {
+        let response = self.bucket.put_object_with_content_type(key, data, content_type).await?;
+        if response.status_code() != 200 {
+            anyhow::bail!("The upload of {} failed with status {}", key, response.status_code());
+        }
+        Ok(format!("{}/{}", self.public_url, key))
+    }
+}
@@ -0,0 +1,211 @@
+use std::convert::Infallible;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use anyhow::Context;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::body::HttpBody;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use reqwest::Url;
+use serenity::model::prelude::AttachmentType;
+use subtle::ConstantTimeEq;
+use tracing::{error, info};
+
+use crate::commands::ring::{apply_tier_ring, check_min_dimension, mime_type, RingOptions, UserRecoverableError};
+use crate::config::{Config, load_env_var};
+use crate::download::{AvatarSource, max_download_bytes};
+use crate::moderation::{Classifier, Moderated};
+
+/// HTTP endpoint ringing images for integrations outside Discord: `POST /ring?tier=<tier>` with the image as the
+/// body and the token as a bearer `Authorization` header answers with the ringed image
+pub struct Webhook {
+    address: SocketAddr,
+    token: String,
+    max_bytes: usize,
+}
+
+impl Webhook {
+    /// The endpoint listening on `CHAOSRING_WEBHOOK_ADDR`, None when unset, which requires `CHAOSRING_WEBHOOK_TOKEN`
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let address = match env::var("CHAOSRING_WEBHOOK_ADDR") {
+            Ok(address) => address.parse::<SocketAddr>()
+                .with_context(|| format!("Invalid CHAOSRING_WEBHOOK_ADDR value: {}", address))?,
+            Err(_) => return Ok(None),
+        };
+        let token = load_env_var("CHAOSRING_WEBHOOK_TOKEN")?;
+        if token.is_empty() {
+            anyhow::bail!("CHAOSRING_WEBHOOK_TOKEN cannot be empty");
+        }
+        Ok(Some(Webhook { address, token, max_bytes: max_download_bytes()? }))
+    }
+
+    /// Serve the requests until the server fails
+    pub async fn serve(self, config: Arc<Config>, classifier: Arc<dyn Classifier>) {
+        let token = Arc::new(self.token);
+        let max_bytes = self.max_bytes;
+        let make_service = make_service_fn(move |_| {
+            let (config, token, classifier) = (config.clone(), token.clone(), classifier.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let (config, token, classifier) = (config.clone(), token.clone(), classifier.clone());
+                    async move { Ok::<_, Infallible>(handle(config, &token, max_bytes, classifier.as_ref(), request).await) }
+                }))
+            }
+        });
+        info!("Listening for webhook requests on {}", self.address);
+        if let Err(err) = Server::bind(&self.address).serve(make_service).await {
+            error!("The webhook server stopped: {}", err);
+        }
+    }
+}
+
+async fn handle(config: Arc<Config>, token: &str, max_bytes: usize, classifier: &dyn Classifier, request: Request<Body>) -> Response<Body> {
+    if request.uri().path() != "/ring" {
+        return text_response(StatusCode::NOT_FOUND, "Not found");
+    }
+    if request.method() != Method::POST {
+        return text_response(StatusCode::METHOD_NOT_ALLOWED, "Expected a POST request");
+    }
+    let authorized = request.headers().get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        // in constant time, so that the time taken doesn't tell how much of the token was guessed
+        .is_some_and(|bearer| bool::from(bearer.as_bytes().ct_eq(token.as_bytes())));
+    if !authorized {
+        return text_response(StatusCode::UNAUTHORIZED, "Missing or invalid bearer token");
+    }
+
+    match ring(config, request, max_bytes, classifier).await {
+        Ok((data, filename)) => Response::builder()
+            .header(CONTENT_TYPE, mime_type(&filename))
+            .body(Body::from(data))
+            .unwrap_or_default(),
+        Err(err) => match err.downcast_ref::<UserRecoverableError>() {
+            Some(user_recoverable_error) => text_response(StatusCode::BAD_REQUEST, &user_recoverable_error.to_string()),
+            None => {
+                error!("Failed to ring the image of a webhook request: {:#}", err);
+                text_response(StatusCode::INTERNAL_SERVER_ERROR, "Unexpected error")
+            }
+        },
+    }
+}
+
+/// Ring the image of the request with the tier named in its query, returning the bytes and file name of the result
+async fn ring(config: Arc<Config>, request: Request<Body>, max_bytes: usize, classifier: &dyn Classifier) -> anyhow::Result<(Vec<u8>, String)> {
+    let url = Url::parse(&format!("http://localhost{}", request.uri()))?;
+    let tier = url.query_pairs()
+        .find(|(key, _)| key == "tier")
+        .map(|(_, value)| value.into_owned())
+        .ok_or_else(|| UserRecoverableError::new("Missing the tier query parameter"))?;
+    let tier = config.tiers.iter()
+        .find(|candidate| candidate.name.eq_ignore_ascii_case(&tier))
+        .ok_or_else(|| UserRecoverableError::new(format!("No tier named {}", tier)))?
+        .clone();
+
+    let mut body = request.into_body();
+    let mut avatar = Vec::new();
+    while let Some(chunk) = body.data().await {
+        avatar.extend_from_slice(&chunk?);
+        if avatar.len() > max_bytes {
+            return Err(UserRecoverableError::new(format!("The image is larger than {} bytes", max_bytes)).into());
+        }
+    }
+
+    let avatar = Moderated { source: &avatar, classifier }.download().await?;
+
+    // the image work would otherwise hold up the other tasks of the runtime, like the Discord gateway
+    let attachments = tokio::task::spawn_blocking(move || {
+        check_min_dimension(&config, &avatar)?;
+        apply_tier_ring(&config, &tier, &avatar, &RingOptions::default())
+    }).await??;
+    match attachments.into_iter().next() {
+        Some(AttachmentType::Bytes { data, filename }) => Ok((data.into_owned(), filename)),
+        _ => anyhow::bail!("No image was produced"),
+    }
+}
+
+fn text_response(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(message.to_string()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageOutputFormat, Rgba, RgbaImage};
+
+    use crate::moderation::NoClassifier;
+    use crate::moderation::tests::FlagEverything;
+
+    use super::*;
+
+    const TOKEN: &str = "s3cret";
+
+    fn config() -> Arc<Config> {
+        let mut config: Config = toml::from_str("tiers = []").unwrap();
+        config.tiers.push(toml::from_str("name = \"DAOist\"\nrole_ids = [1]\nring = \"ring.png\"").unwrap());
+        Arc::new(config)
+    }
+
+    fn request(uri: &str, authorization: Option<&str>, body: Vec<u8>) -> Request<Body> {
+        let mut request = Request::builder().method(Method::POST).uri(uri);
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        request.body(Body::from(body)).unwrap()
+    }
+
+    async fn text(response: Response<Body>) -> String {
+        String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn refuses_a_missing_or_wrong_token() {
+        let response = handle(config(), TOKEN, 1024, &NoClassifier, request("/ring?tier=DAOist", None, vec![])).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = handle(config(), TOKEN, 1024, &NoClassifier, request("/ring?tier=DAOist", Some("Bearer s3cre"), vec![])).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = handle(config(), TOKEN, 1024, &NoClassifier, request("/ring?tier=DAOist", Some(TOKEN), vec![])).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn refuses_an_unknown_tier() {
+        let response = handle(config(), TOKEN, 1024, &NoClassifier, request("/ring?tier=Whale", Some("Bearer s3cret"), vec![])).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(text(response).await.ends_with("No tier named Whale"));
+    }
+
+    #[tokio::test]
+    async fn refuses_a_body_larger_than_the_limit() {
+        let response = handle(config(), TOKEN, 1024, &NoClassifier, request("/ring?tier=daoist", Some("Bearer s3cret"), vec![0; 2048])).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(text(response).await.ends_with("The image is larger than 1024 bytes"));
+    }
+
+    #[tokio::test]
+    async fn refuses_an_image_under_the_hard_minimum_dimension() {
+        let mut config = config();
+        Arc::get_mut(&mut config).unwrap().min_dimension_hard = Some(64);
+        let mut image = Vec::new();
+        RgbaImage::from_pixel(16, 16, Rgba([10, 120, 240, 255]))
+            .write_to(&mut Cursor::new(&mut image), ImageOutputFormat::Png)
+            .unwrap();
+        let response = handle(config, TOKEN, 1024, &NoClassifier, request("/ring?tier=DAOist", Some("Bearer s3cret"), image)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(text(response).await.contains("too small"));
+    }
+
+    #[tokio::test]
+    async fn refuses_an_image_flagged_by_the_classifier() {
+        let response = handle(config(), TOKEN, 1024, &FlagEverything, request("/ring?tier=DAOist", Some("Bearer s3cret"), vec![1, 2, 3])).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(text(response).await.contains("This image cannot be used"));
+    }
+}